@@ -1,4 +1,11 @@
-use std::{io, rc::Rc};
+use std::{
+  collections::HashMap,
+  io,
+  path::{Path, PathBuf},
+  rc::Rc,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 
 use crossterm::{
   event::{
@@ -12,10 +19,7 @@ use crossterm::{
   },
 };
 use futures::{future::FutureExt, select, StreamExt};
-use tokio::{
-  io::AsyncReadExt,
-  sync::mpsc::{UnboundedReceiver, UnboundedSender},
-};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tui::{
   backend::CrosstermBackend,
   layout::{Constraint, Direction, Layout, Margin, Rect},
@@ -25,23 +29,34 @@ use tui_input::Input;
 
 use crate::{
   clipboard::copy,
-  config::{CmdConfig, Config, ProcConfig, ServerConfig},
+  config::{
+    CmdConfig, Config, ProcConfig, RestartConfig, DEFAULT_STOP_TIMEOUT,
+  },
+  ctl::{
+    check_targets, event_channel, run_query, send_event, serve, CtlCommand,
+    CtlEvent, CtlListener, CtlMessage, EventSender, OutputEvents,
+  },
   event::{AppEvent, CopyMove},
   key::Key,
   keymap::Keymap,
+  load_config,
   proc::{CopyMode, Pos, Proc, ProcState, ProcUpdate, StopSignal},
+  search::Search,
   state::{Modal, Scope, State},
   ui_add_proc::render_add_proc,
   ui_confirm_quit::render_confirm_quit,
   ui_keymap::render_keymap,
   ui_procs::{procs_check_hit, procs_get_clicked_index, render_procs},
   ui_remove_proc::render_remove_proc,
+  ui_search::render_search,
   ui_term::{render_term, term_check_hit},
   ui_zoom_tip::render_zoom_tip,
 };
 
 type Term = Terminal<CrosstermBackend<io::Stdout>>;
 
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 enum LoopAction {
   Render,
   Skip,
@@ -50,6 +65,15 @@ enum LoopAction {
 
 pub struct App {
   config: Config,
+  /// Config file to watch and reload. `None` if no config file was found.
+  config_path: Option<PathBuf>,
+  /// `Config::files` of the current config, polled by `watch_config`.
+  config_files: Arc<Mutex<Vec<PathBuf>>>,
+  /// Processes come from command line arguments, so reloading the config
+  /// doesn't touch them.
+  procs_from_args: bool,
+  /// Profile selected with `--profile` or `switch-profile`.
+  profile: Option<String>,
   keymap: Rc<Keymap>,
   terminal: Term,
   state: State,
@@ -57,12 +81,19 @@ pub struct App {
   upd_tx: UnboundedSender<(usize, ProcUpdate)>,
   ev_rx: UnboundedReceiver<AppEvent>,
   ev_tx: UnboundedSender<AppEvent>,
+  ctl_rx: UnboundedReceiver<CtlMessage>,
+  ctl_tx: UnboundedSender<CtlMessage>,
+  /// Events for remote control clients.
+  events: EventSender,
 }
 
 impl App {
   pub fn from_config_file(
     config: Config,
     keymap: Keymap,
+    config_path: Option<PathBuf>,
+    procs_from_args: bool,
+    profile: Option<String>,
   ) -> anyhow::Result<Self> {
     let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
@@ -70,6 +101,7 @@ impl App {
     let (upd_tx, upd_rx) =
       tokio::sync::mpsc::unbounded_channel::<(usize, ProcUpdate)>();
     let (ev_tx, ev_rx) = tokio::sync::mpsc::unbounded_channel::<AppEvent>();
+    let (ctl_tx, ctl_rx) = tokio::sync::mpsc::unbounded_channel();
 
     let state = State {
       scope: Scope::Procs,
@@ -79,10 +111,15 @@ impl App {
       modal: None,
 
       quitting: false,
+      exit_code: None,
     };
 
     let app = App {
+      config_files: Arc::new(Mutex::new(config.files.clone())),
       config,
+      config_path,
+      procs_from_args,
+      profile,
       keymap: Rc::new(keymap),
       terminal,
       state,
@@ -91,11 +128,15 @@ impl App {
 
       ev_rx,
       ev_tx,
+      ctl_rx,
+      ctl_tx,
+      events: event_channel(),
     };
     Ok(app)
   }
 
-  pub async fn run(self) -> anyhow::Result<()> {
+  /// Returns the exit code of mprocs.
+  pub async fn run(self) -> anyhow::Result<i32> {
     enable_raw_mode()?;
 
     let res = self.run_impl().await;
@@ -105,52 +146,21 @@ impl App {
     res
   }
 
-  pub async fn run_impl(mut self) -> anyhow::Result<()> {
+  pub async fn run_impl(mut self) -> anyhow::Result<i32> {
     execute!(io::stdout(), EnterAlternateScreen)?;
     self.terminal.clear()?;
     execute!(io::stdout(), EnableMouseCapture)?;
 
     let (exit_trigger, exit_listener) = triggered::trigger();
 
-    let server_thread = if let Some(ref server_addr) = self.config.server {
-      let server = match server_addr {
-        ServerConfig::Tcp(addr) => tokio::net::TcpListener::bind(addr).await?,
-      };
-
-      let ev_tx = self.ev_tx.clone();
-      let server_thread = tokio::spawn(async move {
-        loop {
-          let on_exit = exit_listener.clone();
-          let mut socket: tokio::net::TcpStream = select! {
-            _ = on_exit.fuse() => break,
-            client = server.accept().fuse() => {
-              if let Ok((socket, _)) = client {
-                socket
-              } else {
-                break;
-              }
-            }
-          };
-
-          let ctl_tx = ev_tx.clone();
-          let on_exit = exit_listener.clone();
-          tokio::spawn(async move {
-            let mut buf: Vec<u8> = Vec::with_capacity(32);
-            select! {
-              _ = on_exit.fuse() => return,
-              count = socket.read_to_end(&mut buf).fuse() => {
-                if count.is_err() {
-                  return;
-                }
-              }
-            };
-            let msg: AppEvent = serde_yaml::from_slice(buf.as_slice()).unwrap();
-            // log::info!("Received remote command: {:?}", msg);
-            ctl_tx.send(msg).unwrap();
-          });
-        }
-      });
-      Some(server_thread)
+    let server = match &self.config.server {
+      Some(server) => Some(CtlListener::bind(server).await?),
+      None => CtlListener::bind_project().await,
+    };
+    let server_thread = if let Some(server) = server {
+      let ctl_tx = self.ctl_tx.clone();
+      let events = self.events.clone();
+      Some(tokio::spawn(serve(server, ctl_tx, events, exit_listener)))
     } else {
       None
     };
@@ -168,7 +178,7 @@ impl App {
     result
   }
 
-  async fn main_loop(mut self) -> anyhow::Result<()> {
+  async fn main_loop(mut self) -> anyhow::Result<i32> {
     let mut input = EventStream::new();
 
     let mut last_term_size = {
@@ -176,6 +186,7 @@ impl App {
       self.start_procs(area)?;
       (area.width, area.height)
     };
+    self.watch_config();
 
     let mut render_needed = true;
     loop {
@@ -208,6 +219,9 @@ impl App {
               Modal::AddProc { input } => {
                 render_add_proc(f.size(), f, input);
               }
+              Modal::Search { input } => {
+                render_search(f.size(), f, input);
+              }
               Modal::RemoveProc { id: _ } => {
                 render_remove_proc(f.size(), f);
               }
@@ -219,6 +233,7 @@ impl App {
         })?;
       }
 
+      let selected = self.state.get_current_proc().map(|proc| proc.id);
       let loop_action = select! {
         event = input.next().fuse() => {
           self.handle_input(event)
@@ -237,8 +252,39 @@ impl App {
             LoopAction::Skip
           }
         }
+        msg = self.ctl_rx.recv().fuse() => {
+          if let Some((cmd, result_tx)) = msg {
+            let (action, result) = match cmd {
+              CtlCommand::Event(event) => {
+                match check_targets(&event, &self.state) {
+                  Ok(()) => {
+                    (self.handle_event(&event), Ok(serde_json::Value::Null))
+                  }
+                  Err(err) => (LoopAction::Skip, Err(err)),
+                }
+              }
+              CtlCommand::Query(query) => (
+                LoopAction::Skip,
+                run_query(&query, &self.state, &self.config),
+              ),
+            };
+            let _ = result_tx.send(result);
+            action
+          } else {
+            LoopAction::Skip
+          }
+        }
       };
 
+      if let Some(proc) = self.state.get_current_proc() {
+        if selected != Some(proc.id) {
+          let event = CtlEvent::Selected {
+            proc: proc.name.clone(),
+          };
+          send_event(&self.events, event, &proc.tags);
+        }
+      }
+
       if self.state.quitting && self.state.all_procs_down() {
         break;
       }
@@ -254,7 +300,11 @@ impl App {
       };
     }
 
-    Ok(())
+    for proc in &mut self.state.procs {
+      proc.kill_orphans();
+    }
+
+    Ok(self.state.exit_code.unwrap_or(0))
   }
 
   fn start_procs(&mut self, size: Rect) -> anyhow::Result<()> {
@@ -262,16 +312,150 @@ impl App {
       .config
       .procs
       .iter()
-      .map(|proc_cfg| {
-        Proc::new(proc_cfg.name.clone(), proc_cfg, self.upd_tx.clone(), size)
-      })
+      .map(|proc_cfg| self.new_proc(proc_cfg, size))
       .collect::<Vec<_>>();
 
     self.state.procs.append(&mut procs);
+    self.state.start_waiting_procs();
 
     Ok(())
   }
 
+  fn new_proc(&self, cfg: &ProcConfig, size: Rect) -> Proc {
+    let output = OutputEvents::new(
+      cfg.name.clone(),
+      cfg.tags.clone(),
+      self.events.clone(),
+    );
+    Proc::with_output(
+      cfg.name.clone(),
+      cfg,
+      self.upd_tx.clone(),
+      size,
+      vec![Arc::new(Mutex::new(output))],
+    )
+  }
+
+  /// Polls the modification time of the config file and sends
+  /// `ReloadConfig` when it changes.
+  fn watch_config(&self) {
+    if self.config_path.is_none() {
+      return;
+    }
+    let files = self.config_files.clone();
+    let ev_tx = self.ev_tx.clone();
+    tokio::spawn(async move {
+      let modified = || {
+        let files = files.lock().unwrap().clone();
+        files
+          .into_iter()
+          .map(|path| {
+            let modified =
+              std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+          })
+          .collect::<HashMap<_, _>>()
+      };
+      let mut last_modified = modified();
+      loop {
+        tokio::time::sleep(CONFIG_POLL_INTERVAL).await;
+        let cur_modified = modified();
+        // Files that were added by a reload are only compared from the next
+        // poll on.
+        let changed = cur_modified.iter().any(|(path, modified)| {
+          matches!(last_modified.get(path), Some(last) if last != modified)
+        });
+        last_modified = cur_modified;
+        if changed && ev_tx.send(AppEvent::ReloadConfig).is_err() {
+          break;
+        }
+      }
+    });
+  }
+
+  /// Loads the config again and applies the changes. On error the current
+  /// config is kept.
+  fn reload_config(&mut self) -> anyhow::Result<()> {
+    let (mut config, keymap, ctx) = load_config(self.config_path.clone())?;
+    if let Some(ctx) = ctx {
+      self.config_path = Some(ctx.path);
+    }
+
+    if let Some(profile) = &self.profile {
+      config.apply_profile(profile)?;
+    }
+
+    config.server = self.config.server.take();
+    if self.procs_from_args {
+      config.procs = std::mem::take(&mut self.config.procs);
+    } else {
+      self.update_procs(&config.procs);
+    }
+
+    *self.config_files.lock().unwrap() = config.files.clone();
+    self.config = config;
+    self.keymap = Rc::new(keymap);
+    Ok(())
+  }
+
+  /// Adds new processes, stops and removes deleted ones and restarts
+  /// processes whose command, environment or working directory changed.
+  fn update_procs(&mut self, new_procs: &[ProcConfig]) {
+    let size = self.get_layout().term_area();
+
+    for old_cfg in &self.config.procs {
+      if new_procs.iter().all(|cfg| cfg.name != old_cfg.name) {
+        for proc in self.state.procs.iter_mut() {
+          if proc.name == old_cfg.name {
+            proc.to_remove = true;
+            proc.to_restart = false;
+            if proc.is_up() {
+              proc.stop();
+            }
+          }
+        }
+      }
+    }
+
+    for cfg in new_procs {
+      match self.config.procs.iter().find(|old| old.name == cfg.name) {
+        Some(old_cfg) => {
+          let changed = old_cfg.cmd != cfg.cmd
+            || old_cfg.env != cfg.env
+            || old_cfg.cwd != cfg.cwd;
+          for proc in self.state.procs.iter_mut() {
+            if proc.name == cfg.name {
+              proc.update_config(cfg);
+              if changed && proc.is_up() {
+                proc.stop();
+                proc.to_restart = true;
+              }
+            }
+          }
+        }
+        None => {
+          let proc = self.new_proc(cfg, size);
+          self.state.procs.push(proc);
+        }
+      }
+    }
+
+    self.remove_stopped_procs();
+    self.state.start_waiting_procs();
+  }
+
+  /// Removes processes deleted from the config once they are down.
+  fn remove_stopped_procs(&mut self) {
+    self
+      .state
+      .procs
+      .retain(|proc| !proc.to_remove || proc.is_up());
+    self.state.selected = self
+      .state
+      .selected
+      .min(self.state.procs.len().saturating_sub(1));
+  }
+
   fn handle_input(
     &mut self,
     event: Option<crossterm::Result<Event>>,
@@ -291,6 +475,7 @@ impl App {
     {
       let mut ret: Option<LoopAction> = None;
       let mut reset_modal = false;
+      let mut new_search = None;
       if let Some(modal) = &mut self.state.modal {
         match modal {
           Modal::AddProc { input } => {
@@ -325,6 +510,32 @@ impl App {
               ret = Some(LoopAction::Render);
             }
           }
+          Modal::Search { input } => {
+            match event {
+              Event::Key(KeyEvent {
+                code: KeyCode::Enter,
+                modifiers,
+              }) if modifiers.is_empty() => {
+                reset_modal = true;
+                new_search = Some(Search::new(input.value()));
+                ret = Some(LoopAction::Render);
+              }
+              Event::Key(KeyEvent {
+                code: KeyCode::Esc,
+                modifiers,
+              }) if modifiers.is_empty() => {
+                reset_modal = true;
+                ret = Some(LoopAction::Render);
+              }
+              _ => (),
+            }
+
+            let req = tui_input::backend::crossterm::to_input_request(event);
+            if let Some(req) = req {
+              input.handle(req);
+              ret = Some(LoopAction::Render);
+            }
+          }
           Modal::RemoveProc { id } => {
             match event {
               Event::Key(KeyEvent {
@@ -332,7 +543,13 @@ impl App {
                 modifiers,
               }) if modifiers.is_empty() => {
                 reset_modal = true;
-                self.ev_tx.send(AppEvent::RemoveProc { id: *id }).unwrap();
+                self
+                  .ev_tx
+                  .send(AppEvent::RemoveProc {
+                    id: Some(*id),
+                    proc: None,
+                  })
+                  .unwrap();
                 // Skip because RemoveProc event will immediately rerender.
                 ret = Some(LoopAction::Skip);
               }
@@ -378,6 +595,13 @@ impl App {
       if reset_modal {
         self.state.modal = None;
       }
+      // An empty query clears the search.
+      if let Some(search) = new_search {
+        if let Some(proc) = self.state.get_current_proc_mut() {
+          proc.search = search;
+          proc.find_next(true);
+        }
+      }
       if let Some(ret) = ret {
         return ret;
       }
@@ -394,7 +618,7 @@ impl App {
           match self.state.scope {
             Scope::Procs => LoopAction::Skip,
             Scope::Term | Scope::TermZoom => {
-              self.handle_event(&AppEvent::SendKey { key })
+              self.handle_event(&AppEvent::SendKey { key, proc: None })
             }
           }
         }
@@ -556,44 +780,13 @@ impl App {
         LoopAction::Render
       }
 
-      AppEvent::StartProc => {
-        if let Some(proc) = self.state.get_current_proc_mut() {
-          proc.start();
-        }
-        LoopAction::Skip
-      }
-      AppEvent::TermProc => {
-        if let Some(proc) = self.state.get_current_proc_mut() {
-          proc.stop();
-        }
-        LoopAction::Skip
-      }
-      AppEvent::KillProc => {
-        if let Some(proc) = self.state.get_current_proc_mut() {
-          proc.kill();
-        }
-        LoopAction::Skip
-      }
-      AppEvent::RestartProc => {
-        if let Some(proc) = self.state.get_current_proc_mut() {
-          if proc.is_up() {
-            proc.stop();
-            proc.to_restart = true;
-          } else {
-            proc.start();
-          }
-        }
-        LoopAction::Skip
-      }
-      AppEvent::ForceRestartProc => {
-        if let Some(proc) = self.state.get_current_proc_mut() {
-          if proc.is_up() {
-            proc.kill();
-            proc.to_restart = true;
-          } else {
-            proc.start();
-          }
-        }
+      AppEvent::StartProc { .. }
+      | AppEvent::TermProc { .. }
+      | AppEvent::KillProc { .. }
+      | AppEvent::RestartProc { .. }
+      | AppEvent::ForceRestartProc { .. }
+      | AppEvent::SendKey { .. } => {
+        self.state.handle_proc_event(event);
         LoopAction::Skip
       }
 
@@ -632,8 +825,8 @@ impl App {
         LoopAction::Render
       }
       AppEvent::AddProc { cmd } => {
-        let proc = Proc::new(
-          cmd.to_string(),
+        let size = self.get_layout().term_area();
+        let proc = self.new_proc(
           &ProcConfig {
             name: cmd.to_string(),
             cmd: CmdConfig::Shell {
@@ -643,9 +836,20 @@ impl App {
             env: None,
             autostart: true,
             stop: StopSignal::default(),
+            stop_timeout: DEFAULT_STOP_TIMEOUT,
+            restart: RestartConfig::default(),
+            depends_on: Vec::new(),
+            tags: Vec::new(),
+            ready_when: None,
+            watch: None,
+            log: self
+              .config
+              .log
+              .as_ref()
+              .map(|log| log.resolve(cmd, Path::new(""))),
+            scrollback: self.config.scrollback,
           },
-          self.upd_tx.clone(),
-          self.get_layout().term_area(),
+          size,
         );
         self.state.procs.push(proc);
         LoopAction::Render
@@ -666,11 +870,20 @@ impl App {
           None => LoopAction::Skip,
         }
       }
-      AppEvent::RemoveProc { id } => {
+      AppEvent::RemoveProc { id, proc } => {
+        let ids = match id {
+          Some(id) => vec![*id],
+          None => self
+            .state
+            .target_procs_mut(proc.as_ref())
+            .into_iter()
+            .map(|proc| proc.id)
+            .collect(),
+        };
         self
           .state
           .procs
-          .retain(|proc| proc.is_up() || proc.id != *id);
+          .retain(|proc| proc.is_up() || !ids.contains(&proc.id));
         LoopAction::Render
       }
 
@@ -679,7 +892,7 @@ impl App {
           Some(proc) => match &mut proc.inst {
             ProcState::None => false,
             ProcState::Some(inst) => {
-              let screen = inst.vt.read().unwrap().screen().clone();
+              let screen = inst.vt().read().unwrap().screen().clone();
               let y = (screen.size().0 - 1) as i32;
               proc.copy_mode = CopyMode::Start(screen, Pos { y, x: 0 });
               true
@@ -704,7 +917,7 @@ impl App {
           match &proc.inst {
             ProcState::None => (),
             ProcState::Some(inst) => {
-              let vt = inst.vt.read().unwrap();
+              let vt = inst.vt().read().unwrap();
               let screen = vt.screen();
               match &mut proc.copy_mode {
                 CopyMode::None(_) => (),
@@ -763,11 +976,52 @@ impl App {
         LoopAction::Render
       }
 
-      AppEvent::SendKey { key } => {
+      AppEvent::SearchStart => {
+        self.state.modal = Some(Modal::Search {
+          input: Input::default(),
+        });
+        LoopAction::Render
+      }
+      AppEvent::SearchNext => {
         if let Some(proc) = self.state.get_current_proc_mut() {
-          proc.send_key(key);
+          proc.find_next(true);
         }
-        LoopAction::Skip
+        LoopAction::Render
+      }
+      AppEvent::SearchPrev => {
+        if let Some(proc) = self.state.get_current_proc_mut() {
+          proc.find_next(false);
+        }
+        LoopAction::Render
+      }
+
+      AppEvent::SwitchProfile { name } => {
+        match self.config.profile_procs(name) {
+          Ok(names) => {
+            self.profile = Some(name.clone());
+            for proc in self.state.procs.iter_mut() {
+              if names.contains(&proc.name) {
+                if !proc.is_up() {
+                  proc.start_or_wait();
+                }
+              } else {
+                proc.waiting_for_deps = false;
+                if proc.is_up() {
+                  proc.stop();
+                }
+              }
+            }
+            self.state.start_waiting_procs();
+          }
+          Err(err) => log::error!("Failed to switch profile: {}", err),
+        }
+        LoopAction::Render
+      }
+      AppEvent::ReloadConfig => {
+        if let Err(err) = self.reload_config() {
+          log::error!("Failed to reload config: {}", err);
+        }
+        LoopAction::Render
       }
     }
   }
@@ -785,16 +1039,78 @@ impl App {
         }
         LoopAction::Skip
       }
-      ProcUpdate::Stopped => {
-        if let Some(proc) = self.state.get_proc_mut(event.0) {
+      ProcUpdate::Stopped(status) => {
+        let quitting = self.state.quitting;
+        let index = self.state.procs.iter().position(|p| p.id == event.0);
+        if let Some(index) = index {
+          let proc = &mut self.state.procs[index];
+          proc.exit_status = Some(status);
+          let stopped = CtlEvent::Stopped {
+            proc: proc.name.clone(),
+            exit_code: status.code,
+            signal: status.signal,
+          };
+          send_event(&self.events, stopped, &proc.tags);
           if proc.to_restart {
             proc.start();
             proc.to_restart = false;
+          } else if !quitting {
+            proc.schedule_auto_restart(status.success());
+          }
+          self.state.apply_exit_policy(&self.config.on_exit, index);
+        }
+        self.remove_stopped_procs();
+        LoopAction::Render
+      }
+      ProcUpdate::Started => {
+        if let Some(proc) = self.state.get_proc_mut(event.0) {
+          if proc.is_up() {
+            let event = CtlEvent::Started {
+              proc: proc.name.clone(),
+            };
+            send_event(&self.events, event, &proc.tags);
           }
         }
+        self.state.start_waiting_procs();
+        LoopAction::Render
+      }
+      ProcUpdate::Ready => {
+        if let Some(proc) = self.state.get_proc_mut(event.0) {
+          if proc.is_up() {
+            proc.ready = true;
+            let event = CtlEvent::Ready {
+              proc: proc.name.clone(),
+            };
+            send_event(&self.events, event, &proc.tags);
+          }
+        }
+        self.state.start_waiting_procs();
+        LoopAction::Render
+      }
+      ProcUpdate::StopTimer => {
+        if let Some(proc) = self.state.get_proc_mut(event.0) {
+          proc.check_stop_timeout();
+        }
+        LoopAction::Render
+      }
+      ProcUpdate::FilesChanged => {
+        if self.state.quitting {
+          return LoopAction::Skip;
+        }
+        if let Some(proc) = self.state.get_proc_mut(event.0) {
+          proc.restart_on_change();
+        }
+        LoopAction::Render
+      }
+      ProcUpdate::AutoRestart => {
+        if self.state.quitting {
+          return LoopAction::Skip;
+        }
+        if let Some(proc) = self.state.get_proc_mut(event.0) {
+          proc.auto_restart();
+        }
         LoopAction::Render
       }
-      ProcUpdate::Started => LoopAction::Render,
     }
   }
 
@@ -9,8 +9,25 @@ pub struct Options {
   #[arg(short = 'c', long = "config", value_name = "PATH")]
   pub config: Option<PathBuf>,
 
-  /// Remote control server address. Example: 127.0.0.1:4050.
-  #[arg(short = 's', long = "server", value_name = "HOST:PORT")]
+  /// Only start processes of this profile (and their dependencies)
+  #[arg(short = 'p', long = "profile", value_name = "NAME")]
+  pub profile: Option<String>,
+
+  /// Validate the config and exit
+  #[arg(long = "check")]
+  pub check: bool,
+
+  /// Print JSON Schema of the config file and exit
+  #[arg(long = "print-schema")]
+  pub print_schema: bool,
+
+  /// Print output of all processes to stdout instead of showing the UI
+  #[arg(long = "headless")]
+  pub headless: bool,
+
+  /// Remote control server address. Examples: 127.0.0.1:4050,
+  /// unix:/tmp/mprocs.sock.
+  #[arg(short = 's', long = "server", value_name = "HOST:PORT|unix:PATH")]
   pub server: Option<String>,
 
   /// Send yaml/json encoded command to running mprocs
@@ -86,11 +86,12 @@ fn copy_impl(s: &str, provider: &Provider) -> Result<()> {
         .stderr(Stdio::null())
         .spawn()
         .unwrap();
-      std::io::Write::write_all(
+      let written = std::io::Write::write_all(
         &mut child.stdin.as_ref().unwrap(),
         s.as_bytes(),
-      )?;
+      );
       child.wait()?;
+      written?;
     }
 
     #[cfg(windows)]
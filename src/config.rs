@@ -1,12 +1,22 @@
-use std::{ffi::OsString, path::PathBuf, str::FromStr};
+use std::{
+  collections::{HashMap, HashSet},
+  ffi::OsString,
+  fmt::Display,
+  path::{Path, PathBuf},
+  str::FromStr,
+  time::Duration,
+};
 
 use anyhow::{bail, Result};
 use indexmap::IndexMap;
 use portable_pty::CommandBuilder;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use serde_yaml::Value;
 
 use crate::{
+  dotenv::parse_dotenv,
+  interpolate::{interpolate, interpolate_shell, Vars},
   proc::StopSignal,
   settings::Settings,
   yaml_val::{value_to_string, Val},
@@ -18,10 +28,23 @@ pub struct ConfigContext {
 
 pub struct Config {
   pub procs: Vec<ProcConfig>,
+  /// Problems that don't prevent loading the config, like unknown fields.
+  pub warnings: Vec<String>,
+  /// Named subsets of `procs`.
+  pub profiles: IndexMap<String, Vec<String>>,
+  pub on_exit: OnExitConfig,
+  /// Default `log` for processes that are not defined in a config file.
+  pub log: Option<LogConfig>,
+  /// Default `scrollback` for processes that are not defined in a config
+  /// file.
+  pub scrollback: Scrollback,
   pub server: Option<ServerConfig>,
   pub hide_keymap_window: bool,
   pub mouse_scroll_speed: usize,
   pub proc_list_width: usize,
+  /// Files the config was read from: config files, included files and env
+  /// files. A change in any of them reloads the config.
+  pub files: Vec<PathBuf>,
 }
 
 impl Config {
@@ -30,15 +53,49 @@ impl Config {
     ctx: &ConfigContext,
     settings: &Settings,
   ) -> Result<Config> {
-    let config = Val::new(value)?;
-    let config = config.as_object()?;
+    let config_val = Val::new(value)?;
+    let config = config_val.as_object()?;
+
+    let vars = Vars {
+      config_dir: dunce::canonicalize(&ctx.path).ok().and_then(|path| {
+        path.parent().map(|dir| dir.to_string_lossy().to_string())
+      }),
+      port_offset: match config.get(&Value::from("port_offset")) {
+        Some(port_offset) => port_offset.as_usize()?,
+        None => 0,
+      },
+    };
+
+    let mut env_files = Vec::new();
+    let templates = match config.get(&Value::from("templates")) {
+      Some(templates) => templates.as_object()?,
+      None => IndexMap::new(),
+    };
 
     let procs = if let Some(procs) = config.get(&Value::from("procs")) {
       procs
         .as_object()?
         .into_iter()
         .map(|(name, proc)| {
-          ProcConfig::from_val(value_to_string(&name)?, proc, ctx)
+          let name = value_to_string(&name)?;
+          match extend_proc(&proc, &templates)? {
+            Some(extended) => ProcConfig::from_val(
+              name,
+              proc.derive(&extended)?,
+              ctx,
+              &vars,
+              settings,
+              &mut env_files,
+            ),
+            None => ProcConfig::from_val(
+              name,
+              proc,
+              ctx,
+              &vars,
+              settings,
+              &mut env_files,
+            ),
+          }
         })
         .collect::<Result<Vec<_>>>()?
         .into_iter()
@@ -48,6 +105,39 @@ impl Config {
       Vec::new()
     };
 
+    check_depends_on(&procs)?;
+
+    let mut warnings = Vec::new();
+    check_fields(&config_val, &mut warnings)?;
+
+    let profiles = match config.get(&Value::from("profiles")) {
+      Some(profiles) => profiles
+        .as_object()?
+        .into_iter()
+        .map(|(name, names)| {
+          let name = value_to_string(&name)?;
+          let names = names
+            .as_array()?
+            .into_iter()
+            .map(|v| {
+              let proc = v.as_str()?;
+              if !procs.iter().any(|p| p.name == proc) {
+                bail!(v.error_at(format!("Unknown process \"{}\"", proc)));
+              }
+              Ok(proc.to_owned())
+            })
+            .collect::<Result<Vec<_>>>()?;
+          Ok((name, names))
+        })
+        .collect::<Result<IndexMap<_, _>>>()?,
+      None => IndexMap::new(),
+    };
+
+    let on_exit = match config.get(&Value::from("on_exit")) {
+      Some(on_exit) => OnExitConfig::from_val(on_exit, &procs)?,
+      None => OnExitConfig::default(),
+    };
+
     let server = if let Some(addr) = config.get(&Value::from("server")) {
       Some(ServerConfig::from_str(addr.as_str()?)?)
     } else {
@@ -56,10 +146,16 @@ impl Config {
 
     let config = Config {
       procs,
+      warnings,
+      profiles,
+      on_exit,
+      log: settings.log.clone(),
+      scrollback: settings.scrollback,
       server,
       hide_keymap_window: settings.hide_keymap_window,
       mouse_scroll_speed: settings.mouse_scroll_speed,
       proc_list_width: settings.proc_list_width,
+      files: env_files,
     };
 
     Ok(config)
@@ -68,14 +164,477 @@ impl Config {
   pub fn make_default(settings: &Settings) -> Self {
     Self {
       procs: Vec::new(),
+      warnings: Vec::new(),
+      profiles: IndexMap::new(),
+      on_exit: OnExitConfig::default(),
+      log: settings.log.clone(),
+      scrollback: settings.scrollback,
       server: None,
       hide_keymap_window: settings.hide_keymap_window,
       mouse_scroll_speed: settings.mouse_scroll_speed,
       proc_list_width: settings.proc_list_width,
+      files: Vec::new(),
+    }
+  }
+
+  /// Names of processes in a profile together with their dependencies.
+  pub fn profile_procs(&self, profile: &str) -> Result<HashSet<String>> {
+    let names = match self.profiles.get(profile) {
+      Some(names) => names,
+      None => bail!("Unknown profile \"{}\".", profile),
+    };
+
+    let mut procs = HashSet::new();
+    let mut queue = names.clone();
+    while let Some(name) = queue.pop() {
+      if let Some(proc) = self.procs.iter().find(|p| p.name == name) {
+        queue.extend(proc.depends_on.iter().cloned());
+      }
+      procs.insert(name);
+    }
+    Ok(procs)
+  }
+
+  /// Makes only the processes of the profile start automatically.
+  pub fn apply_profile(&mut self, profile: &str) -> Result<()> {
+    let names = self.profile_procs(profile)?;
+    for proc in &mut self.procs {
+      proc.autostart = names.contains(&proc.name);
+    }
+    Ok(())
+  }
+
+  /// The config in the format of config files, with templates, includes and
+  /// defaults resolved. Values of `env` are redacted.
+  pub fn to_json(&self) -> serde_json::Value {
+    let procs = self
+      .procs
+      .iter()
+      .map(|proc| (proc.name.clone(), proc.to_json()))
+      .collect::<serde_json::Map<_, _>>();
+    let on_exit = &self.on_exit;
+    let procs_on_exit = on_exit
+      .procs
+      .iter()
+      .map(|(name, action)| (name.clone(), json!(action.as_str())))
+      .collect::<serde_json::Map<_, _>>();
+    let on_exit = skip_nulls(json!({
+      "any_fails": on_exit.any_fails.map(|action| action.as_str()),
+      "all_done": on_exit.all_done.then_some("quit"),
+      "proc": procs_on_exit,
+    }));
+    let server = self.server.as_ref().map(|server| server.to_string());
+
+    skip_nulls(json!({
+      "procs": procs,
+      "profiles": self.profiles,
+      "on_exit": on_exit,
+      "server": server,
+      "hide_keymap_window": self.hide_keymap_window,
+      "mouse_scroll_speed": self.mouse_scroll_speed,
+      "proc_list_width": self.proc_list_width,
+    }))
+  }
+}
+
+/// Removes `null` fields of an object, so that only set fields are left.
+fn skip_nulls(mut value: serde_json::Value) -> serde_json::Value {
+  if let Some(map) = value.as_object_mut() {
+    map.retain(|_, value| !value.is_null());
+  }
+  value
+}
+
+/// Shown instead of the values of `env` by `get-config`.
+const REDACTED_ENV: &str = "<redacted>";
+
+pub const CONFIG_FIELDS: [&str; 15] = [
+  "procs",
+  "templates",
+  "profiles",
+  "on_exit",
+  "include",
+  "server",
+  "port_offset",
+  "log",
+  "scrollback",
+  "hide_keymap_window",
+  "mouse_scroll_speed",
+  "proc_list_width",
+  "keymap_procs",
+  "keymap_term",
+  "keymap_copy",
+];
+pub const PROC_FIELDS: [&str; 17] = [
+  "shell",
+  "cmd",
+  "cwd",
+  "env",
+  "env_file",
+  "add_path",
+  "autostart",
+  "stop",
+  "stop_timeout",
+  "restart",
+  "depends_on",
+  "tags",
+  "ready_when",
+  "watch",
+  "log",
+  "scrollback",
+  "extends",
+];
+pub const RESTART_FIELDS: [&str; 4] =
+  ["policy", "max_retries", "delay", "max_delay"];
+pub const READY_FIELDS: [&str; 4] = ["log", "port", "cmd", "interval"];
+pub const WATCH_FIELDS: [&str; 3] = ["paths", "ignore", "debounce"];
+pub const ON_EXIT_FIELDS: [&str; 3] = ["any_fails", "all_done", "proc"];
+pub const LOG_FIELDS: [&str; 4] = ["file", "rotate", "keep", "strip_ansi"];
+
+/// Adds warnings for fields that mprocs doesn't know, which are usually
+/// typos.
+fn check_fields(config: &Val, warnings: &mut Vec<String>) -> Result<()> {
+  fn check(val: &Val, known: &[&str], warnings: &mut Vec<String>) {
+    if let Value::Mapping(map) = val.raw() {
+      for (key, _) in map {
+        if !matches!(key.as_str(), Some(key) if known.contains(&key)) {
+          let key = value_to_string(key).unwrap_or_default();
+          warnings.push(val.message_at(format!("Unknown field \"{}\"", key)));
+        }
+      }
+    }
+  }
+
+  check(config, &CONFIG_FIELDS, warnings);
+  let config = config.as_object()?;
+
+  if let Some(log) = config.get(&Value::from("log")) {
+    check(log, &LOG_FIELDS, warnings);
+  }
+
+  if let Some(procs) = config.get(&Value::from("procs")) {
+    for (_, proc) in procs.as_object()? {
+      if !proc.raw().is_mapping() {
+        continue;
+      }
+      check(&proc, &PROC_FIELDS, warnings);
+      let proc = proc.as_object()?;
+      for (field, known) in [
+        ("restart", &RESTART_FIELDS[..]),
+        ("ready_when", &READY_FIELDS[..]),
+        ("watch", &WATCH_FIELDS[..]),
+        ("log", &LOG_FIELDS[..]),
+      ] {
+        if let Some(val) = proc.get(&Value::from(field)) {
+          check(val, known, warnings);
+        }
+      }
+    }
+  }
+
+  if let Some(on_exit) = config.get(&Value::from("on_exit")) {
+    check(on_exit, &ON_EXIT_FIELDS, warnings);
+  }
+
+  if let Some(templates) = config.get(&Value::from("templates")) {
+    for (_, template) in templates.as_object()? {
+      check(&template, &INHERITED_FIELDS, warnings);
+    }
+  }
+
+  Ok(())
+}
+
+/// Reads dotenv files listed in `env_file`. Relative paths are resolved
+/// against the config directory. Later files override earlier ones. The
+/// paths are added to `files`.
+fn read_env_files(
+  val: &Val,
+  ctx: &ConfigContext,
+  files: &mut Vec<PathBuf>,
+) -> Result<IndexMap<String, Option<String>>> {
+  let paths = match val.raw() {
+    Value::String(_) => vec![val.derive(val.raw())?],
+    _ => val.as_array()?,
+  };
+  let config_dir = dunce::canonicalize(&ctx.path)?
+    .parent()
+    .map(|dir| dir.to_path_buf())
+    .unwrap_or_default();
+
+  let mut env: IndexMap<String, Option<String>> = IndexMap::new();
+  for path_val in paths {
+    let path = path_val.as_str()?;
+    let full_path = config_dir.join(path);
+    if !files.contains(&full_path) {
+      files.push(full_path.clone());
+    }
+    let src = std::fs::read_to_string(&full_path).map_err(|err| {
+      path_val
+        .error_at(format!("Failed to read env file \"{}\": {}", path, err))
+    })?;
+    let vars = parse_dotenv(&src, |name| match env.get(name) {
+      Some(Some(value)) => Some(value.clone()),
+      _ => std::env::var(name).ok(),
+    })
+    .map_err(|msg| path_val.error_at(format!("{} in \"{}\"", msg, path)))?;
+    for (k, v) in vars {
+      env.insert(k, Some(v));
+    }
+  }
+
+  Ok(env)
+}
+
+/// Reads a string and replaces `${NAME}` references in it.
+fn interpolate_val(
+  val: &Val,
+  lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+  interpolate(val.as_str()?, lookup).map_err(|msg| val.error_at(msg))
+}
+
+/// Reads a shell command and replaces `${NAME}` references to variables
+/// provided by mprocs in it. Other `${...}` are left for the shell to expand,
+/// so that quoting and values from the environment keep their meaning.
+fn interpolate_shell_val(
+  val: &Val,
+  lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+  Ok(interpolate_shell(val.as_str()?, lookup))
+}
+
+/// Top-level fields that are merged key by key when configs are included.
+const MERGED_BY_KEY: [&str; 6] = [
+  "procs",
+  "templates",
+  "profiles",
+  "keymap_procs",
+  "keymap_term",
+  "keymap_copy",
+];
+
+/// Merges a config over a config it includes. Fields listed in
+/// `MERGED_BY_KEY` are merged entry by entry (a keymap with `reset: true`
+/// replaces the included one), other fields are replaced.
+pub fn merge_config(base: &mut Value, over: Value) {
+  let (base, over) = match (base, over) {
+    (Value::Mapping(base), Value::Mapping(over)) => (base, over),
+    (base, over) => {
+      *base = over;
+      return;
+    }
+  };
+
+  for (key, value) in over {
+    let by_key =
+      matches!(key.as_str(), Some(key) if MERGED_BY_KEY.contains(&key));
+    match (base.get_mut(&key), value) {
+      (Some(Value::Mapping(base_map)), Value::Mapping(map))
+        if by_key
+          && map.get(&Value::from("reset")) != Some(&Value::from(true)) =>
+      {
+        for (k, v) in map {
+          base_map.insert(k, v);
+        }
+      }
+      (_, value) => {
+        base.insert(key, value);
+      }
+    }
+  }
+}
+
+/// Rewrites paths of an included config, which are relative to its own
+/// directory, to paths in `dir`: `cwd` (also with the `<CONFIG_DIR>` prefix),
+/// `env_file` and `log` of procs and templates, and the global `log`.
+pub fn rebase_included_paths(value: &mut Value, dir: &Path) {
+  let map = match value {
+    Value::Mapping(map) => map,
+    _ => return,
+  };
+  if let Some(log) = map.get_mut(&Value::from("log")) {
+    rebase_log(log, dir);
+  }
+  for section in ["procs", "templates"] {
+    if let Some(Value::Mapping(procs)) = map.get_mut(&Value::from(section)) {
+      for (_, proc) in procs.iter_mut() {
+        if let Value::Mapping(proc) = proc {
+          rebase_proc_paths(proc, dir);
+        }
+      }
+    }
+  }
+}
+
+fn rebase_proc_paths(proc: &mut serde_yaml::Mapping, dir: &Path) {
+  if let Some(Value::String(cwd)) = proc.get_mut(&Value::from("cwd")) {
+    *cwd = match cwd.strip_prefix("<CONFIG_DIR>") {
+      Some(rest) => format!("{}{}", dir.display(), rest),
+      None => rebase_path(cwd, dir),
+    };
+  }
+  match proc.get_mut(&Value::from("env_file")) {
+    Some(Value::String(path)) => *path = rebase_path(path, dir),
+    Some(Value::Sequence(paths)) => {
+      for path in paths {
+        if let Value::String(path) = path {
+          *path = rebase_path(path, dir);
+        }
+      }
+    }
+    _ => (),
+  }
+  if let Some(log) = proc.get_mut(&Value::from("log")) {
+    rebase_log(log, dir);
+  }
+}
+
+fn rebase_log(log: &mut Value, dir: &Path) {
+  let file = match log {
+    Value::String(file) => file,
+    Value::Mapping(map) => match map.get_mut(&Value::from("file")) {
+      Some(Value::String(file)) => file,
+      _ => return,
+    },
+    _ => return,
+  };
+  *file = rebase_path(file, dir);
+}
+
+/// Joins a relative path to `dir`. Paths starting with a variable, like
+/// `${HOME}/app`, are kept.
+fn rebase_path(path: &str, dir: &Path) -> String {
+  if path.starts_with('$') || Path::new(path).is_absolute() {
+    path.to_string()
+  } else {
+    dir.join(path).to_string_lossy().to_string()
+  }
+}
+
+/// Fields a process inherits from the template named in `extends`.
+pub const INHERITED_FIELDS: [&str; 7] = [
+  "env",
+  "cwd",
+  "add_path",
+  "stop",
+  "log",
+  "scrollback",
+  "tags",
+];
+
+/// Returns the process value with fields inherited from its template, or
+/// `None` if it doesn't extend a template. Fields set on the process win,
+/// `env` is merged by variable.
+fn extend_proc(
+  proc: &Val,
+  templates: &IndexMap<Value, Val>,
+) -> Result<Option<Value>> {
+  let map = match proc.raw() {
+    Value::Mapping(map) => map,
+    _ => return Ok(None),
+  };
+  let extends = match proc.as_object()?.get(&Value::from("extends")) {
+    Some(extends) => {
+      let name = extends.as_str()?;
+      match templates.get(&Value::from(name)) {
+        Some(template) => template.as_object()?,
+        None => {
+          bail!(extends.error_at(format!("Unknown template \"{}\"", name)))
+        }
+      }
+    }
+    None => return Ok(None),
+  };
+
+  let mut map = map.clone();
+  for field in INHERITED_FIELDS {
+    let inherited = match extends.get(&Value::from(field)) {
+      Some(inherited) => inherited.raw(),
+      None => continue,
+    };
+    match (map.get_mut(&Value::from(field)), inherited) {
+      (None, inherited) => {
+        map.insert(Value::from(field), inherited.clone());
+      }
+      (Some(Value::Mapping(env)), Value::Mapping(inherited))
+        if field == "env" =>
+      {
+        for (k, v) in inherited {
+          if !env.contains_key(k) {
+            env.insert(k.clone(), v.clone());
+          }
+        }
+      }
+      (Some(_), _) => (),
     }
   }
+
+  Ok(Some(Value::Mapping(map)))
 }
 
+/// Fails if a process depends on an unknown process or if dependencies form a
+/// cycle.
+fn check_depends_on(procs: &[ProcConfig]) -> Result<()> {
+  let by_name = procs
+    .iter()
+    .map(|proc| (proc.name.as_str(), proc))
+    .collect::<HashMap<_, _>>();
+
+  for proc in procs {
+    for dep in &proc.depends_on {
+      if !by_name.contains_key(dep.as_str()) {
+        bail!(
+          "Process \"{}\" depends on unknown process \"{}\".",
+          proc.name,
+          dep
+        );
+      }
+    }
+  }
+
+  // `false` - being visited, `true` - visited.
+  fn visit<'a>(
+    name: &'a str,
+    by_name: &HashMap<&'a str, &'a ProcConfig>,
+    visited: &mut HashMap<&'a str, bool>,
+    path: &mut Vec<&'a str>,
+  ) -> Result<()> {
+    match visited.get(name) {
+      Some(true) => return Ok(()),
+      Some(false) => {
+        let start = path.iter().position(|n| *n == name).unwrap_or(0);
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name);
+        bail!(
+          "Dependency cycle between processes: {}.",
+          cycle.join(" -> ")
+        );
+      }
+      None => (),
+    }
+
+    visited.insert(name, false);
+    path.push(name);
+    for dep in &by_name[name].depends_on {
+      visit(dep, by_name, visited, path)?;
+    }
+    path.pop();
+    visited.insert(name, true);
+
+    Ok(())
+  }
+
+  let mut visited = HashMap::new();
+  for proc in procs {
+    visit(&proc.name, &by_name, &mut visited, &mut Vec::new())?;
+  }
+
+  Ok(())
+}
+
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct ProcConfig {
   pub name: String,
   pub cmd: CmdConfig,
@@ -84,6 +643,16 @@ pub struct ProcConfig {
   pub autostart: bool,
 
   pub stop: StopSignal,
+  /// Kill the process if it is still running this long after being stopped.
+  pub stop_timeout: Duration,
+  pub restart: RestartConfig,
+  pub depends_on: Vec<String>,
+  /// Lets remote commands select the process with `tag:<TAG>`.
+  pub tags: Vec<String>,
+  pub ready_when: Option<ReadyConfig>,
+  pub watch: Option<WatchConfig>,
+  pub log: Option<LogConfig>,
+  pub scrollback: Scrollback,
 }
 
 impl ProcConfig {
@@ -91,26 +660,44 @@ impl ProcConfig {
     name: String,
     val: Val,
     ctx: &ConfigContext,
+    vars: &Vars,
+    settings: &Settings,
+    env_files: &mut Vec<PathBuf>,
   ) -> Result<Option<ProcConfig>> {
+    let config_dir = ctx.path.parent().unwrap_or_else(|| Path::new(""));
+    let default_log = settings.log.as_ref();
+    let resolved_default_log =
+      default_log.map(|log| log.resolve(&name, config_dir));
     match val.raw() {
       Value::Null => Ok(None),
-      Value::Bool(_) => todo!(),
-      Value::Number(_) => todo!(),
-      Value::String(shell) => Ok(Some(ProcConfig {
-        name,
+      Value::Bool(_) | Value::Number(_) => {
+        Err(val.error_at("Expected object, string, array of strings or null"))
+      }
+      Value::String(_) => Ok(Some(ProcConfig {
         cmd: CmdConfig::Shell {
-          shell: shell.to_owned(),
+          shell: interpolate_shell_val(&val, |var| {
+            vars.get_provided(var, &name)
+          })?,
         },
+        name,
         cwd: None,
         env: None,
         autostart: true,
         stop: StopSignal::default(),
+        stop_timeout: DEFAULT_STOP_TIMEOUT,
+        restart: RestartConfig::default(),
+        depends_on: Vec::new(),
+        tags: Vec::new(),
+        ready_when: None,
+        watch: None,
+        log: resolved_default_log,
+        scrollback: settings.scrollback,
       })),
       Value::Sequence(_) => {
         let cmd = val.as_array()?;
         let cmd = cmd
           .into_iter()
-          .map(|item| item.as_str().map(|s| s.to_owned()))
+          .map(|item| interpolate_val(&item, |var| vars.get(var, &name, None)))
           .collect::<Result<Vec<_>>>()?;
 
         Ok(Some(ProcConfig {
@@ -120,45 +707,21 @@ impl ProcConfig {
           env: None,
           autostart: true,
           stop: StopSignal::default(),
+          stop_timeout: DEFAULT_STOP_TIMEOUT,
+          restart: RestartConfig::default(),
+          depends_on: Vec::new(),
+          tags: Vec::new(),
+          ready_when: None,
+          watch: None,
+          log: resolved_default_log,
+          scrollback: settings.scrollback,
         }))
       }
       Value::Mapping(_) => {
         let map = val.as_object()?;
 
-        let cmd = {
-          let shell = map.get(&Value::from("shell"));
-          let cmd = map.get(&Value::from("cmd"));
-
-          match (shell, cmd) {
-            (None, Some(cmd)) => CmdConfig::Cmd {
-              cmd: cmd
-                .as_array()?
-                .into_iter()
-                .map(|v| v.as_str().map(|s| s.to_owned()))
-                .collect::<Result<Vec<_>>>()?,
-            },
-            (Some(shell), None) => CmdConfig::Shell {
-              shell: shell.as_str()?.to_owned(),
-            },
-            (None, None) => todo!(),
-            (Some(_), Some(_)) => todo!(),
-          }
-        };
-
-        let cwd = match map.get(&Value::from("cwd")) {
-          Some(cwd) => {
-            let cwd = cwd.as_str()?;
-            let mut buf = OsString::new();
-            if let Some(rest) = cwd.strip_prefix("<CONFIG_DIR>") {
-              if let Some(parent) = dunce::canonicalize(&ctx.path)?.parent() {
-                buf.push(parent);
-              }
-              buf.push(rest);
-            } else {
-              buf.push(cwd);
-            }
-            Some(buf)
-          }
+        let env_file = match map.get(&Value::from("env_file")) {
+          Some(env_file) => Some(read_env_files(env_file, ctx, env_files)?),
           None => None,
         };
 
@@ -170,7 +733,9 @@ impl ProcConfig {
               .map(|(k, v)| {
                 let v = match v.raw() {
                   Value::Null => Ok(None),
-                  Value::String(v) => Ok(Some(v.to_owned())),
+                  Value::String(_) => Ok(Some(interpolate_val(&v, |var| {
+                    vars.get(var, &name, env_file.as_ref())
+                  })?)),
                   _ => Err(v.error_at("Expected string or null")),
                 };
                 Ok((value_to_string(&k)?, v?))
@@ -180,6 +745,59 @@ impl ProcConfig {
           }
           None => None,
         };
+        // Explicit `env` takes precedence over `env_file`.
+        let env = match (env_file, env) {
+          (Some(mut file_env), Some(env)) => {
+            file_env.extend(env);
+            Some(file_env)
+          }
+          (file_env, env) => env.or(file_env),
+        };
+        let lookup = |var: &str| vars.get(var, &name, env.as_ref());
+
+        let cmd =
+          {
+            let shell = map.get(&Value::from("shell"));
+            let cmd = map.get(&Value::from("cmd"));
+
+            match (shell, cmd) {
+              (None, Some(cmd)) => CmdConfig::Cmd {
+                cmd: cmd
+                  .as_array()?
+                  .into_iter()
+                  .map(|v| interpolate_val(&v, lookup))
+                  .collect::<Result<Vec<_>>>()?,
+              },
+              (Some(shell), None) => CmdConfig::Shell {
+                shell: interpolate_shell_val(shell, |var| {
+                  vars.get_provided(var, &name)
+                })?,
+              },
+              (None, None) => {
+                bail!(val.error_at("Expected \"shell\" or \"cmd\" field"))
+              }
+              (Some(_), Some(_)) => bail!(val
+                .error_at("Expected only one of \"shell\" or \"cmd\" fields")),
+            }
+          };
+
+        let cwd = match map.get(&Value::from("cwd")) {
+          Some(cwd) => {
+            let cwd = interpolate_val(cwd, lookup)?;
+            let mut buf = OsString::new();
+            if let Some(rest) = cwd.strip_prefix("<CONFIG_DIR>") {
+              if let Some(parent) = dunce::canonicalize(&ctx.path)?.parent() {
+                buf.push(parent);
+              }
+              buf.push(rest);
+            } else {
+              buf.push(cwd);
+            }
+            Some(buf)
+          }
+          None => None,
+        };
+
         let env = match map.get(&Value::from("add_path")) {
           Some(add_path) => {
             let extra_paths = match add_path.raw() {
@@ -225,11 +843,61 @@ impl ProcConfig {
           .map_or(Ok(true), |v| v.as_bool())?;
 
         let stop_signal = if let Some(val) = map.get(&Value::from("stop")) {
-          serde_yaml::from_value(val.raw().clone())?
+          serde_yaml::from_value(val.raw().clone())
+            .map_err(|err| val.error_at(err.to_string()))?
         } else {
           StopSignal::default()
         };
 
+        let stop_timeout = match map.get(&Value::from("stop_timeout")) {
+          Some(val) => val.as_duration()?,
+          None => DEFAULT_STOP_TIMEOUT,
+        };
+
+        let restart = match map.get(&Value::from("restart")) {
+          Some(val) => RestartConfig::from_val(val)?,
+          None => RestartConfig::default(),
+        };
+
+        let depends_on = match map.get(&Value::from("depends_on")) {
+          Some(depends_on) => depends_on
+            .as_array()?
+            .into_iter()
+            .map(|v| v.as_str().map(|s| s.to_owned()))
+            .collect::<Result<Vec<_>>>()?,
+          None => Vec::new(),
+        };
+
+        let tags = match map.get(&Value::from("tags")) {
+          Some(tags) => tags
+            .as_array()?
+            .into_iter()
+            .map(|v| v.as_str().map(|s| s.to_owned()))
+            .collect::<Result<Vec<_>>>()?,
+          None => Vec::new(),
+        };
+
+        let ready_when = match map.get(&Value::from("ready_when")) {
+          Some(val) => Some(ReadyConfig::from_val(val)?),
+          None => None,
+        };
+
+        let watch = match map.get(&Value::from("watch")) {
+          Some(val) => Some(WatchConfig::from_val(val)?),
+          None => None,
+        };
+
+        let log = match map.get(&Value::from("log")) {
+          Some(val) => LogConfig::from_val(val, default_log)?
+            .map(|log| log.resolve(&name, config_dir)),
+          None => resolved_default_log,
+        };
+
+        let scrollback = match map.get(&Value::from("scrollback")) {
+          Some(val) => Scrollback::from_val(val)?,
+          None => settings.scrollback,
+        };
+
         Ok(Some(ProcConfig {
           name,
           cmd,
@@ -237,23 +905,465 @@ impl ProcConfig {
           env,
           autostart,
           stop: stop_signal,
+          stop_timeout,
+          restart,
+          depends_on,
+          tags,
+          ready_when,
+          watch,
+          log,
+          scrollback,
         }))
       }
     }
   }
+
+  /// Values of `env` are replaced with `REDACTED_ENV`, since they often hold
+  /// secrets from `env_file`. Unset variables stay `null`.
+  pub fn to_json(&self) -> serde_json::Value {
+    let (shell, cmd) = match &self.cmd {
+      CmdConfig::Shell { shell } => (Some(shell), None),
+      CmdConfig::Cmd { cmd } => (None, Some(cmd)),
+    };
+    let restart = &self.restart;
+    let restart = skip_nulls(json!({
+      "policy": restart.policy.as_str(),
+      "max_retries": restart.max_retries,
+      "delay": restart.delay.as_secs_f64(),
+      "max_delay": restart.max_delay.as_secs_f64(),
+    }));
+    let ready_when = self.ready_when.as_ref().map(|ready| {
+      skip_nulls(json!({
+        "log": ready.log,
+        "port": ready.port,
+        "cmd": ready.cmd,
+        "interval": ready.interval.as_secs_f64(),
+      }))
+    });
+    let watch = self.watch.as_ref().map(|watch| {
+      json!({
+        "paths": watch.paths,
+        "ignore": watch.ignore,
+        "debounce": watch.debounce.as_secs_f64(),
+      })
+    });
+    let env = self.env.as_ref().map(|env| {
+      env
+        .iter()
+        .map(|(name, value)| (name, value.as_ref().map(|_| REDACTED_ENV)))
+        .collect::<IndexMap<_, _>>()
+    });
+    let log = self.log.as_ref().map(|log| {
+      skip_nulls(json!({
+        "file": log.file.to_string_lossy(),
+        "rotate": log.rotate,
+        "keep": log.keep,
+        "strip_ansi": log.strip_ansi,
+      }))
+    });
+
+    skip_nulls(json!({
+      "shell": shell,
+      "cmd": cmd,
+      "cwd": self.cwd.as_ref().map(|cwd| cwd.to_string_lossy()),
+      "env": env,
+      "autostart": self.autostart,
+      "stop": self.stop,
+      "stop_timeout": self.stop_timeout.as_secs_f64(),
+      "restart": restart,
+      "depends_on": self.depends_on,
+      "tags": self.tags,
+      "ready_when": ready_when,
+      "watch": watch,
+      "log": log,
+      "scrollback": self.scrollback.to_json(),
+    }))
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RestartPolicy {
+  Never,
+  OnFailure,
+  Always,
+}
+
+impl RestartPolicy {
+  fn from_val(val: &Val) -> Result<Self> {
+    match val.as_str()? {
+      "never" => Ok(RestartPolicy::Never),
+      "on-failure" => Ok(RestartPolicy::OnFailure),
+      "always" => Ok(RestartPolicy::Always),
+      _ => {
+        Err(val.error_at("Expected \"never\", \"on-failure\" or \"always\""))
+      }
+    }
+  }
+
+  fn as_str(&self) -> &'static str {
+    match self {
+      RestartPolicy::Never => "never",
+      RestartPolicy::OnFailure => "on-failure",
+      RestartPolicy::Always => "always",
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct RestartConfig {
+  pub policy: RestartPolicy,
+  /// Maximum number of consecutive automatic restarts. `None` means no limit.
+  pub max_retries: Option<usize>,
+  /// Delay before the first restart. Doubled after every failed attempt.
+  pub delay: Duration,
+  pub max_delay: Duration,
+}
+
+impl Default for RestartConfig {
+  fn default() -> Self {
+    Self {
+      policy: RestartPolicy::Never,
+      max_retries: None,
+      delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(30),
+    }
+  }
+}
+
+impl RestartConfig {
+  fn from_val(val: &Val) -> Result<Self> {
+    match val.raw() {
+      Value::String(_) => Ok(RestartConfig {
+        policy: RestartPolicy::from_val(val)?,
+        ..Default::default()
+      }),
+      Value::Mapping(_) => {
+        let map = val.as_object()?;
+        let mut restart = RestartConfig::default();
+        if let Some(policy) = map.get(&Value::from("policy")) {
+          restart.policy = RestartPolicy::from_val(policy)?;
+        }
+        if let Some(max_retries) = map.get(&Value::from("max_retries")) {
+          restart.max_retries = Some(max_retries.as_usize()?);
+        }
+        if let Some(delay) = map.get(&Value::from("delay")) {
+          restart.delay = delay.as_duration()?;
+        }
+        if let Some(max_delay) = map.get(&Value::from("max_delay")) {
+          restart.max_delay = max_delay.as_duration()?;
+        }
+        Ok(restart)
+      }
+      _ => Err(val.error_at("Expected string or object")),
+    }
+  }
+
+  /// Delay before the restart attempt number `attempt` (starting from 0).
+  pub fn delay_for(&self, attempt: usize) -> Duration {
+    let factor = 1u32.checked_shl(attempt as u32).unwrap_or(u32::MAX);
+    self.delay.saturating_mul(factor).min(self.max_delay)
+  }
+}
+
+/// What to do when a process exits on its own.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExitAction {
+  /// Stop all processes.
+  StopAll,
+  /// Stop all processes and quit.
+  Quit,
+  /// Stop all processes and quit with the exit code of the process.
+  QuitWithCode,
+}
+
+impl ExitAction {
+  fn from_val(val: &Val) -> Result<Self> {
+    match val.as_str()? {
+      "stop-all" => Ok(ExitAction::StopAll),
+      "quit" => Ok(ExitAction::Quit),
+      "quit-with-code" => Ok(ExitAction::QuitWithCode),
+      _ => Err(
+        val.error_at("Expected \"stop-all\", \"quit\" or \"quit-with-code\""),
+      ),
+    }
+  }
+
+  fn as_str(&self) -> &'static str {
+    match self {
+      ExitAction::StopAll => "stop-all",
+      ExitAction::Quit => "quit",
+      ExitAction::QuitWithCode => "quit-with-code",
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OnExitConfig {
+  /// Applied when a process fails and is not restarted.
+  pub any_fails: Option<ExitAction>,
+  /// Quit once all processes are down.
+  pub all_done: bool,
+  /// Applied whenever the process exits. Takes precedence over `any_fails`.
+  pub procs: IndexMap<String, ExitAction>,
+}
+
+impl OnExitConfig {
+  fn from_val(val: &Val, procs: &[ProcConfig]) -> Result<Self> {
+    let map = val.as_object()?;
+    let mut on_exit = OnExitConfig::default();
+    if let Some(action) = map.get(&Value::from("any_fails")) {
+      on_exit.any_fails = Some(ExitAction::from_val(action)?);
+    }
+    if let Some(action) = map.get(&Value::from("all_done")) {
+      if action.as_str()? != "quit" {
+        bail!(action.error_at("Expected \"quit\""));
+      }
+      on_exit.all_done = true;
+    }
+    if let Some(actions) = map.get(&Value::from("proc")) {
+      for (name, action) in actions.as_object()? {
+        let name = value_to_string(&name)?;
+        if !procs.iter().any(|p| p.name == name) {
+          bail!(action.error_at(format!("Unknown process \"{}\"", name)));
+        }
+        on_exit.procs.insert(name, ExitAction::from_val(&action)?);
+      }
+    }
+    Ok(on_exit)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadyConfig {
+  /// Text to look for in the process output.
+  pub log: Option<String>,
+  /// Address to connect to.
+  pub port: Option<String>,
+  /// Shell command that must exit with code 0.
+  pub cmd: Option<String>,
+  pub interval: Duration,
+}
+
+impl ReadyConfig {
+  fn from_val(val: &Val) -> Result<Self> {
+    let map = val.as_object()?;
+
+    let log = match map.get(&Value::from("log")) {
+      Some(log) => Some(log.as_str()?.to_owned()),
+      None => None,
+    };
+    let port = match map.get(&Value::from("port")) {
+      Some(port) => match port.raw() {
+        Value::Number(_) => Some(format!("localhost:{}", port.as_usize()?)),
+        Value::String(addr) => Some(addr.to_owned()),
+        _ => bail!(port.error_at("Expected port number or address")),
+      },
+      None => None,
+    };
+    let cmd = match map.get(&Value::from("cmd")) {
+      Some(cmd) => Some(cmd.as_str()?.to_owned()),
+      None => None,
+    };
+    let interval = match map.get(&Value::from("interval")) {
+      Some(interval) => interval.as_duration()?,
+      None => Duration::from_millis(500),
+    };
+
+    if log.is_none() && port.is_none() && cmd.is_none() {
+      bail!(val.error_at("Expected at least one of \"log\", \"port\", \"cmd\""));
+    }
+
+    Ok(ReadyConfig {
+      log,
+      port,
+      cmd,
+      interval,
+    })
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+  /// Log file path. `{name}` is replaced with the process name.
+  pub file: PathBuf,
+  /// Rotate the file once it grows past this many bytes.
+  pub rotate: Option<u64>,
+  /// Number of rotated files to keep.
+  pub keep: usize,
+  /// Remove terminal escape sequences from the output.
+  pub strip_ansi: bool,
+}
+
+impl Default for LogConfig {
+  fn default() -> Self {
+    Self {
+      file: PathBuf::from("logs/{name}.log"),
+      rotate: None,
+      keep: 5,
+      strip_ansi: false,
+    }
+  }
+}
+
+impl LogConfig {
+  /// Parses a `log` field. Omitted fields are taken from `base`. Returns
+  /// `None` for `false`.
+  pub fn from_val(val: &Val, base: Option<&LogConfig>) -> Result<Option<Self>> {
+    let mut log = base.cloned().unwrap_or_default();
+    match val.raw() {
+      Value::Bool(false) => return Ok(None),
+      Value::Bool(true) => (),
+      Value::String(file) => log.file = PathBuf::from(file),
+      Value::Mapping(_) => {
+        let map = val.as_object()?;
+        if let Some(file) = map.get(&Value::from("file")) {
+          log.file = PathBuf::from(file.as_str()?);
+        }
+        if let Some(rotate) = map.get(&Value::from("rotate")) {
+          log.rotate = Some(rotate.as_size()?);
+        }
+        if let Some(keep) = map.get(&Value::from("keep")) {
+          log.keep = keep.as_usize()?;
+        }
+        if let Some(strip_ansi) = map.get(&Value::from("strip_ansi")) {
+          log.strip_ansi = strip_ansi.as_bool()?;
+        }
+      }
+      _ => bail!(val.error_at("Expected string, object or boolean")),
+    }
+    Ok(Some(log))
+  }
+
+  /// Log config of a single process. A relative path is resolved against
+  /// `dir`.
+  pub fn resolve(&self, name: &str, dir: &Path) -> LogConfig {
+    let name = name.replace(['/', '\\'], "_");
+    let file = self.file.to_string_lossy().replace("{name}", &name);
+    LogConfig {
+      file: dir.join(file),
+      ..self.clone()
+    }
+  }
+}
+
+/// How much output of a process is kept for scrolling back.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scrollback {
+  Lines(usize),
+  /// All output is kept, compressed, see `history`.
+  Unlimited,
+}
+
+impl Default for Scrollback {
+  fn default() -> Self {
+    Scrollback::Lines(1000)
+  }
+}
+
+impl Scrollback {
+  pub fn from_val(val: &Val) -> Result<Self> {
+    match val.raw() {
+      Value::Number(_) => Ok(Scrollback::Lines(val.as_usize()?)),
+      Value::String(s) if s == "unlimited" => Ok(Scrollback::Unlimited),
+      _ => bail!(val.error_at("Expected number of lines or \"unlimited\"")),
+    }
+  }
+
+  fn to_json(self) -> serde_json::Value {
+    match self {
+      Scrollback::Lines(lines) => json!(lines),
+      Scrollback::Unlimited => json!("unlimited"),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchConfig {
+  /// Glob patterns of files to watch, relative to the process cwd.
+  pub paths: Vec<String>,
+  /// Glob patterns of files and directories to skip.
+  pub ignore: Vec<String>,
+  /// Time without further changes before the process is restarted.
+  pub debounce: Duration,
+}
+
+impl WatchConfig {
+  fn from_val(val: &Val) -> Result<Self> {
+    let patterns = |val: &Val| -> Result<Vec<String>> {
+      match val.raw() {
+        Value::String(pattern) => Ok(vec![pattern.to_owned()]),
+        Value::Sequence(_) => val
+          .as_array()?
+          .into_iter()
+          .map(|v| v.as_str().map(|s| s.to_owned()))
+          .collect::<Result<Vec<_>>>(),
+        _ => Err(val.error_at("Expected string or array")),
+      }
+    };
+
+    let (paths, ignore, debounce) = match val.raw() {
+      Value::Mapping(_) => {
+        let map = val.as_object()?;
+        let paths = match map.get(&Value::from("paths")) {
+          Some(paths) => patterns(paths)?,
+          None => bail!(val.error_at("Expected \"paths\"")),
+        };
+        let ignore = match map.get(&Value::from("ignore")) {
+          Some(ignore) => patterns(ignore)?,
+          None => Vec::new(),
+        };
+        let debounce = match map.get(&Value::from("debounce")) {
+          Some(debounce) => debounce.as_duration()?,
+          None => Duration::from_millis(300),
+        };
+        (paths, ignore, debounce)
+      }
+      _ => (patterns(val)?, Vec::new(), Duration::from_millis(300)),
+    };
+
+    if paths.is_empty() {
+      bail!(val.error_at("Expected at least one path"));
+    }
+
+    Ok(WatchConfig {
+      paths,
+      ignore,
+      debounce,
+    })
+  }
 }
 
 pub enum ServerConfig {
   Tcp(String),
+  /// Unix domain socket that only the user can connect to.
+  #[cfg(not(windows))]
+  Unix(PathBuf),
 }
 
 impl ServerConfig {
+  /// `unix:<PATH>` is a Unix domain socket, anything else a TCP address.
   pub fn from_str(server_addr: &str) -> Result<Self> {
-    Ok(Self::Tcp(server_addr.to_string()))
+    match server_addr.strip_prefix("unix:") {
+      #[cfg(not(windows))]
+      Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+      #[cfg(windows)]
+      Some(_) => bail!("Unix domain sockets are not supported on Windows"),
+      None => Ok(Self::Tcp(server_addr.to_string())),
+    }
   }
 }
 
-#[derive(Deserialize, Serialize)]
+impl Display for ServerConfig {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ServerConfig::Tcp(addr) => f.write_str(addr),
+      #[cfg(not(windows))]
+      ServerConfig::Unix(path) => write!(f, "unix:{}", path.display()),
+    }
+  }
+}
+
+#[derive(Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum CmdConfig {
   Cmd { cmd: Vec<String> },
@@ -301,3 +1411,450 @@ impl From<&ProcConfig> for CommandBuilder {
     cmd
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+
+  use super::*;
+
+  fn proc_with_deps(name: &str, deps: &[&str]) -> ProcConfig {
+    ProcConfig {
+      name: name.to_string(),
+      cmd: CmdConfig::Shell {
+        shell: name.to_string(),
+      },
+      cwd: None,
+      env: None,
+      autostart: true,
+      stop: StopSignal::default(),
+      stop_timeout: DEFAULT_STOP_TIMEOUT,
+      restart: RestartConfig::default(),
+      depends_on: deps.iter().map(|s| s.to_string()).collect(),
+      tags: Vec::new(),
+      ready_when: None,
+      watch: None,
+      log: None,
+      scrollback: Scrollback::default(),
+    }
+  }
+
+  #[test]
+  fn merge_includes() {
+    let mut base: Value = serde_yaml::from_str(
+      "procs: {a: x, b: y}\nkeymap_procs: {q: quit}\nserver: a:1",
+    )
+    .unwrap();
+    let over: Value = serde_yaml::from_str(
+      "procs: {b: z, c: w}\nkeymap_procs: {reset: true}\nserver: b:2",
+    )
+    .unwrap();
+    merge_config(&mut base, over);
+    let expected: Value = serde_yaml::from_str(
+      "procs: {a: x, b: z, c: w}\nkeymap_procs: {reset: true}\nserver: b:2",
+    )
+    .unwrap();
+    assert_eq!(base, expected);
+  }
+
+  #[test]
+  fn rebase_includes() {
+    let mut value: Value = serde_yaml::from_str(
+      "log: logs/{name}.log\n\
+       templates:\n  t: {cwd: '<CONFIG_DIR>/web', env_file: [.env, /a.env]}\n\
+       procs:\n  a: {shell: x, cwd: api, log: {file: a.log}}\n\
+       \x20 b: {shell: x, cwd: '${HOME}/b', env_file: b.env}\n  c: x",
+    )
+    .unwrap();
+    let dir = Path::new("/shared");
+    rebase_included_paths(&mut value, dir);
+
+    let path = |p: &str| Value::from(dir.join(p).to_string_lossy().as_ref());
+    assert_eq!(value["log"], path("logs/{name}.log"));
+    let template = &value["templates"]["t"];
+    assert_eq!(
+      template["cwd"],
+      Value::from(format!("{}/web", dir.display()))
+    );
+    assert_eq!(template["env_file"][0], path(".env"));
+    assert_eq!(template["env_file"][1], Value::from("/a.env"));
+    let procs = &value["procs"];
+    assert_eq!(procs["a"]["cwd"], path("api"));
+    assert_eq!(procs["a"]["log"]["file"], path("a.log"));
+    assert_eq!(procs["b"]["cwd"], Value::from("${HOME}/b"));
+    assert_eq!(procs["b"]["env_file"], path("b.env"));
+    assert_eq!(procs["c"], Value::from("x"));
+  }
+
+  #[test]
+  fn extends_template() {
+    let value: Value = serde_yaml::from_str(
+      "templates:\n  node: {cwd: web, stop: SIGTERM, env: {A: '1', B: '2'}}\n\
+       procs:\n  app: {shell: x, extends: node, cwd: api, env: {B: '3'}}",
+    )
+    .unwrap();
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let config =
+      Config::from_value(&value, &ctx, &Settings::default()).unwrap();
+    let proc = &config.procs[0];
+    assert_eq!(proc.cwd, Some(OsString::from("api")));
+    assert!(matches!(proc.stop, StopSignal::SIGTERM));
+    let env = proc.env.as_ref().unwrap();
+    assert_eq!(env["A"], Some("1".to_string()));
+    assert_eq!(env["B"], Some("3".to_string()));
+  }
+
+  #[test]
+  fn interpolate_vars() {
+    let value: Value = serde_yaml::from_str(
+      "port_offset: 10\n\
+       procs:\n  app: {shell: 'run ${PROC_NAME} ${X}', env: {X: '${PORT_OFFSET}'}}",
+    )
+    .unwrap();
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let config =
+      Config::from_value(&value, &ctx, &Settings::default()).unwrap();
+    assert!(matches!(
+      &config.procs[0].cmd,
+      CmdConfig::Shell { shell } if shell == "run app ${X}"
+    ));
+
+    let value: Value =
+      serde_yaml::from_str("procs:\n  app: {cmd: [run, '${MPROCS_UNSET}']}")
+        .unwrap();
+    let err = Config::from_value(&value, &ctx, &Settings::default())
+      .err()
+      .unwrap();
+    assert_eq!(
+      err.to_string(),
+      "Undefined variable \"MPROCS_UNSET\" at <config>.procs.app.cmd.1"
+    );
+
+    let value: Value = serde_yaml::from_str(
+      "procs:\n  app: 'for f in *; do echo ${f} ${HOME} ${PROC_NAME}; done'",
+    )
+    .unwrap();
+    let config =
+      Config::from_value(&value, &ctx, &Settings::default()).unwrap();
+    assert!(matches!(
+      &config.procs[0].cmd,
+      CmdConfig::Shell { shell }
+        if shell == "for f in *; do echo ${f} ${HOME} app; done"
+    ));
+  }
+
+  #[test]
+  fn profile_with_deps() {
+    let mut config = Config::make_default(&Settings::default());
+    config.procs = vec![
+      proc_with_deps("api", &["db"]),
+      proc_with_deps("db", &[]),
+      proc_with_deps("web", &[]),
+    ];
+    config
+      .profiles
+      .insert("backend".to_string(), vec!["api".to_string()]);
+
+    config.apply_profile("backend").unwrap();
+    let autostart = config
+      .procs
+      .iter()
+      .map(|p| (p.name.as_str(), p.autostart))
+      .collect::<Vec<_>>();
+    assert_eq!(autostart, vec![("api", true), ("db", true), ("web", false)]);
+    assert!(config.apply_profile("frontend").is_err());
+  }
+
+  #[test]
+  fn validation() {
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let load = |src: &str| {
+      let value: Value = serde_yaml::from_str(src).unwrap();
+      Config::from_value(&value, &ctx, &Settings::default())
+    };
+
+    let config =
+      load("procs:\n  a: {shell: x, autostrat: false}\nhide_keymap: true")
+        .unwrap();
+    assert_eq!(
+      config.warnings,
+      vec![
+        "Unknown field \"hide_keymap\" at <config>",
+        "Unknown field \"autostrat\" at <config>.procs.a",
+      ]
+    );
+
+    let err = |src: &str| load(src).err().unwrap().to_string();
+    assert_eq!(
+      err("procs:\n  a: true"),
+      "Expected object, string, array of strings or null at <config>.procs.a"
+    );
+    assert_eq!(
+      err("procs:\n  a: {cwd: x}"),
+      "Expected \"shell\" or \"cmd\" field at <config>.procs.a"
+    );
+    assert_eq!(
+      err("procs:\n  a: {shell: x, cmd: [x]}"),
+      "Expected only one of \"shell\" or \"cmd\" fields at <config>.procs.a"
+    );
+  }
+
+  #[test]
+  fn log_config() {
+    let ctx = ConfigContext {
+      path: PathBuf::from("dir/mprocs.yaml"),
+    };
+    let mut settings = Settings::default();
+    let global: Value =
+      serde_yaml::from_str("log: {rotate: 10MB, strip_ansi: true}").unwrap();
+    settings.merge_value(Val::new(&global).unwrap()).unwrap();
+
+    let value: Value = serde_yaml::from_str(
+      "procs:\n  a: x\n  b: {shell: y, log: {file: b.txt, keep: 2}}\n  \
+       c: {shell: z, log: false}",
+    )
+    .unwrap();
+    let config = Config::from_value(&value, &ctx, &settings).unwrap();
+    let logs = config
+      .procs
+      .iter()
+      .map(|proc| proc.log.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      logs,
+      vec![
+        Some(LogConfig {
+          file: PathBuf::from("dir/logs/a.log"),
+          rotate: Some(10 * 1024 * 1024),
+          keep: 5,
+          strip_ansi: true,
+        }),
+        Some(LogConfig {
+          file: PathBuf::from("dir/b.txt"),
+          rotate: Some(10 * 1024 * 1024),
+          keep: 2,
+          strip_ansi: true,
+        }),
+        None,
+      ]
+    );
+  }
+
+  #[test]
+  fn scrollback() {
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let mut settings = Settings::default();
+    let global: Value = serde_yaml::from_str("scrollback: 5000").unwrap();
+    settings.merge_value(Val::new(&global).unwrap()).unwrap();
+
+    let value: Value = serde_yaml::from_str(
+      "procs:\n  a: x\n  b: {shell: y, scrollback: unlimited}",
+    )
+    .unwrap();
+    let config = Config::from_value(&value, &ctx, &settings).unwrap();
+    let scrollback = config
+      .procs
+      .iter()
+      .map(|proc| proc.scrollback)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      scrollback,
+      vec![Scrollback::Lines(5000), Scrollback::Unlimited]
+    );
+
+    let value: Value =
+      serde_yaml::from_str("procs: {a: {shell: x, scrollback: all}}").unwrap();
+    assert!(Config::from_value(&value, &ctx, &settings).is_err());
+  }
+
+  #[test]
+  fn on_exit() {
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let load = |src: &str| {
+      let value: Value = serde_yaml::from_str(src).unwrap();
+      Config::from_value(&value, &ctx, &Settings::default())
+    };
+
+    let config = load(
+      "procs: {db: x, tests: y}\n\
+       on_exit: {any_fails: stop-all, all_done: quit, proc: {tests: quit-with-code}}",
+    )
+    .unwrap();
+    assert_eq!(config.on_exit.any_fails, Some(ExitAction::StopAll));
+    assert!(config.on_exit.all_done);
+    assert_eq!(
+      config.on_exit.procs.get("tests"),
+      Some(&ExitAction::QuitWithCode)
+    );
+
+    assert_eq!(
+      load("procs: {db: x}\non_exit: {proc: {web: quit}}")
+        .err()
+        .unwrap()
+        .to_string(),
+      "Unknown process \"web\" at <config>.on_exit.proc.web"
+    );
+  }
+
+  #[test]
+  fn config_to_json() {
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let value: Value = serde_yaml::from_str(
+      "procs:\n  \
+       db: {cmd: [pg, -D, data], stop: {send-keys: [<C-c>]},\n    \
+       scrollback: unlimited}\n  \
+       api: {shell: x, env: {A: b, C: null}, restart: on-failure,\n    \
+       depends_on: [db], ready_when: {port: 3000}, watch: src/**,\n    \
+       log: true, stop_timeout: 1.5, tags: [backend]}\n\
+       profiles: {dev: [api]}\n\
+       on_exit: {any_fails: quit}\n\
+       server: 127.0.0.1:4050",
+    )
+    .unwrap();
+    let config =
+      Config::from_value(&value, &ctx, &Settings::default()).unwrap();
+    let json = config.to_json();
+    assert_eq!(
+      json["procs"]["api"],
+      json!({
+        "shell": "x",
+        "env": {"A": "<redacted>", "C": null},
+        "autostart": true,
+        "stop": "SIGTERM",
+        "stop_timeout": 1.5,
+        "restart": {
+          "policy": "on-failure",
+          "delay": 1.0,
+          "max_delay": 30.0,
+        },
+        "depends_on": ["db"],
+        "tags": ["backend"],
+        "ready_when": {"port": "localhost:3000", "interval": 0.5},
+        "watch": {"paths": ["src/**"], "ignore": [], "debounce": 0.3},
+        "log": {"file": "logs/api.log", "keep": 5, "strip_ansi": false},
+        "scrollback": 1000,
+      })
+    );
+    assert_eq!(json["procs"]["db"]["stop"], json!({"send-keys": ["<C-c>"]}));
+    assert_eq!(json["on_exit"], json!({"any_fails": "quit", "proc": {}}));
+
+    // The output is a valid config that loads the same.
+    let value = serde_yaml::to_value(&json).unwrap();
+    let reloaded =
+      Config::from_value(&value, &ctx, &Settings::default()).unwrap();
+    assert_eq!(reloaded.to_json(), json);
+  }
+
+  #[test]
+  fn json_covers_all_fields() {
+    let ctx = ConfigContext {
+      path: PathBuf::from("mprocs.yaml"),
+    };
+    let value: Value = serde_yaml::from_str(
+      "procs:\n  \
+       db: {cmd: [pg], add_path: bin}\n  \
+       api: {shell: x, cwd: api, env: {A: b}, autostart: false,\n    \
+       stop: SIGINT, stop_timeout: 1,\n    \
+       restart: {policy: always, max_retries: 3, delay: 1, max_delay: 2},\n    \
+       depends_on: [db], tags: [backend],\n    \
+       ready_when: {log: up, port: 3000, cmd: check, interval: 1},\n    \
+       watch: {paths: [src], ignore: [target], debounce: 1},\n    \
+       log: {file: api.log, rotate: 1MB, keep: 2, strip_ansi: true},\n    \
+       scrollback: 10}\n\
+       profiles: {dev: [api]}\n\
+       on_exit: {any_fails: quit, all_done: quit, proc: {db: quit}}\n\
+       server: 127.0.0.1:4050\n\
+       hide_keymap_window: true\n\
+       mouse_scroll_speed: 3\n\
+       proc_list_width: 20",
+    )
+    .unwrap();
+    let config =
+      Config::from_value(&value, &ctx, &Settings::default()).unwrap();
+    let json = config.to_json();
+
+    let keys = |values: &[&serde_json::Value]| {
+      values
+        .iter()
+        .flat_map(|value| value.as_object().unwrap().keys().cloned())
+        .collect::<BTreeSet<_>>()
+    };
+    // Fields that are resolved while loading aren't in the output.
+    let fields = |fields: &[&str], resolved: &[&str]| {
+      fields
+        .iter()
+        .filter(|field| !resolved.contains(field))
+        .map(|field| field.to_string())
+        .collect::<BTreeSet<_>>()
+    };
+    assert_eq!(
+      keys(&[&json]),
+      fields(
+        &CONFIG_FIELDS,
+        &[
+          "templates",
+          "include",
+          "port_offset",
+          "log",
+          "scrollback",
+          "keymap_procs",
+          "keymap_term",
+          "keymap_copy",
+        ],
+      )
+    );
+    let api = &json["procs"]["api"];
+    assert_eq!(
+      keys(&[api, &json["procs"]["db"]]),
+      fields(&PROC_FIELDS, &["env_file", "add_path", "extends"])
+    );
+    for (value, known) in [
+      (&api["restart"], &RESTART_FIELDS[..]),
+      (&api["ready_when"], &READY_FIELDS[..]),
+      (&api["watch"], &WATCH_FIELDS[..]),
+      (&api["log"], &LOG_FIELDS[..]),
+      (&json["on_exit"], &ON_EXIT_FIELDS[..]),
+    ] {
+      assert_eq!(keys(&[value]), fields(known, &[]));
+    }
+  }
+
+  #[test]
+  fn depends_on() {
+    assert!(check_depends_on(&[
+      proc_with_deps("api", &["db", "redis"]),
+      proc_with_deps("db", &[]),
+      proc_with_deps("redis", &["db"]),
+    ])
+    .is_ok());
+
+    let err = check_depends_on(&[
+      proc_with_deps("a", &["b"]),
+      proc_with_deps("b", &["c"]),
+      proc_with_deps("c", &["b"]),
+    ])
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Dependency cycle between processes: b -> c -> b."
+    );
+
+    let err = check_depends_on(&[proc_with_deps("a", &["x"])]).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Process \"a\" depends on unknown process \"x\"."
+    );
+  }
+}
@@ -1,15 +1,397 @@
-use serde_yaml::Value;
+//! Remote control of a running mprocs. Clients send requests to the server as
+//! newline-delimited JSON and get a response line for every request.
+
+#[cfg(not(windows))]
+use std::{
+  ffi::OsString,
+  os::unix::{
+    ffi::OsStrExt,
+    fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt},
+  },
+  path::{Path, PathBuf},
+};
+use std::{fmt::Display, io};
+
+use anyhow::bail;
+use futures::{future::FutureExt, select};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+#[cfg(not(windows))]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+  io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite,
+    AsyncWriteExt, BufReader,
+  },
+  net::{TcpListener, TcpStream},
+  sync::{
+    broadcast::{self, error::RecvError},
+    mpsc::UnboundedSender,
+    oneshot,
+  },
+};
 
 use crate::{
-  config::{Config, ServerConfig},
+  config::{Config, ProcConfig, ServerConfig},
   event::AppEvent,
+  proc::{OutputSink, Proc},
+  selector::ProcSelector,
+  state::State,
+  strip_ansi::AnsiStripper,
 };
 
+/// A command with an optional `id` that is copied into the response, e.g.
+/// `{"id": 1, "c": "restart-proc"}`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CtlRequest {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<Value>,
+  #[serde(flatten)]
+  pub cmd: CtlCommand,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum CtlCommand {
+  Event(AppEvent),
+  Query(CtlQuery),
+}
+
+impl<'de> Deserialize<'de> for CtlCommand {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let name = value.get("c").and_then(|c| c.as_str());
+    if matches!(name, Some(name) if CtlQuery::NAMES.contains(&name)) {
+      CtlQuery::deserialize(&value)
+        .map(CtlCommand::Query)
+        .map_err(D::Error::custom)
+    } else {
+      AppEvent::deserialize(&value)
+        .map(CtlCommand::Event)
+        .map_err(D::Error::custom)
+    }
+  }
+}
+
+/// Commands that only read the state of mprocs. Unlike `AppEvent`s, they can't
+/// be bound to keys.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "c", rename_all = "kebab-case")]
+pub enum CtlQuery {
+  ListProcs,
+  GetOutput {
+    /// Must match a single process.
+    proc: ProcSelector,
+    /// Only the last `lines` lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lines: Option<usize>,
+  },
+  GetConfig,
+  /// Turns the connection into a stream of `CtlEvent`s.
+  Subscribe {
+    /// Include lines of process output.
+    #[serde(default)]
+    output: bool,
+    /// Only events of the matching processes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
+}
+
+impl CtlQuery {
+  /// Values of the `"c"` tag of queries.
+  const NAMES: [&'static str; 4] =
+    ["list-procs", "get-output", "get-config", "subscribe"];
+}
+
+/// Sent to subscribed clients, one per line.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum CtlEvent {
+  Started {
+    proc: String,
+  },
+  Stopped {
+    proc: String,
+    exit_code: Option<u32>,
+    signal: Option<i32>,
+  },
+  Ready {
+    proc: String,
+  },
+  Output {
+    proc: String,
+    line: String,
+  },
+  Selected {
+    proc: String,
+  },
+  /// The client didn't keep up and missed events.
+  Lagged {
+    skipped: u64,
+  },
+}
+
+impl CtlEvent {
+  fn proc(&self) -> Option<&str> {
+    match self {
+      CtlEvent::Started { proc }
+      | CtlEvent::Stopped { proc, .. }
+      | CtlEvent::Ready { proc }
+      | CtlEvent::Output { proc, .. }
+      | CtlEvent::Selected { proc } => Some(proc),
+      CtlEvent::Lagged { .. } => None,
+    }
+  }
+}
+
+/// Events that a subscriber hasn't received yet. A slower subscriber misses
+/// events.
+const EVENT_BUFFER: usize = 1024;
+
+/// An event with the tags of its process, so that subscriptions can select
+/// processes by tag.
+#[derive(Clone, Debug)]
+pub struct TaggedEvent {
+  pub event: CtlEvent,
+  pub tags: Vec<String>,
+}
+
+pub type EventSender = broadcast::Sender<TaggedEvent>;
+
+pub fn event_channel() -> EventSender {
+  broadcast::channel(EVENT_BUFFER).0
+}
+
+/// Sends an event of a process with the given tags to subscribers.
+pub fn send_event(events: &EventSender, event: CtlEvent, tags: &[String]) {
+  let _ = events.send(TaggedEvent {
+    event,
+    tags: tags.to_vec(),
+  });
+}
+
+/// Sends lines of process output, without escape sequences, to subscribers.
+pub struct OutputEvents {
+  proc: String,
+  tags: Vec<String>,
+  events: EventSender,
+  stripper: AnsiStripper,
+  buf: Vec<u8>,
+}
+
+impl OutputEvents {
+  pub fn new(proc: String, tags: Vec<String>, events: EventSender) -> Self {
+    OutputEvents {
+      proc,
+      tags,
+      events,
+      stripper: AnsiStripper::new(),
+      buf: Vec::new(),
+    }
+  }
+}
+
+impl OutputSink for OutputEvents {
+  fn write(&mut self, bytes: &[u8]) {
+    if self.events.receiver_count() == 0 {
+      self.buf.clear();
+      return;
+    }
+    self.stripper.strip_into(bytes, &mut self.buf);
+    while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+      let rest = self.buf.split_off(pos + 1);
+      let line = std::mem::replace(&mut self.buf, rest);
+      let event = CtlEvent::Output {
+        proc: self.proc.clone(),
+        line: String::from_utf8_lossy(&line[..pos]).into_owned(),
+      };
+      send_event(&self.events, event, &self.tags);
+    }
+  }
+
+  fn update_config(&mut self, cfg: &ProcConfig) {
+    self.tags = cfg.tags.clone();
+  }
+}
+
+/// Has either `result` (`null` for commands that return nothing) or `error`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CtlResponse {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<Value>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub result: Option<Value>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<CtlError>,
+}
+
+impl CtlResponse {
+  pub fn new(id: Option<Value>, result: Result<Value, CtlError>) -> Self {
+    match result {
+      Ok(result) => CtlResponse {
+        id,
+        result: Some(result),
+        error: None,
+      },
+      Err(error) => CtlResponse {
+        id,
+        result: None,
+        error: Some(error),
+      },
+    }
+  }
+
+  pub fn into_result(self) -> Result<Value, CtlError> {
+    match self.error {
+      Some(error) => Err(error),
+      None => Ok(self.result.unwrap_or(Value::Null)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CtlErrorCode {
+  /// The request is not valid JSON.
+  ParseError,
+  /// The request is not a known command.
+  InvalidRequest,
+  /// The command refers to a process that doesn't exist.
+  NotFound,
+  /// mprocs is shutting down.
+  Unavailable,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CtlError {
+  pub code: CtlErrorCode,
+  pub message: String,
+}
+
+impl CtlError {
+  pub fn new(code: CtlErrorCode, message: impl Into<String>) -> Self {
+    CtlError {
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+impl Display for CtlError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let code = serde_json::to_value(self.code).unwrap_or_default();
+    write!(f, "{}: {}", code.as_str().unwrap_or_default(), self.message)
+  }
+}
+
+/// Commands are passed to the app with a channel for the result.
+pub type CtlMessage = (CtlCommand, oneshot::Sender<Result<Value, CtlError>>);
+
+pub fn run_query(
+  query: &CtlQuery,
+  state: &State,
+  config: &Config,
+) -> Result<Value, CtlError> {
+  match query {
+    CtlQuery::ListProcs => {
+      Ok(Value::Array(state.procs.iter().map(proc_info).collect()))
+    }
+    CtlQuery::GetOutput {
+      proc: selector,
+      lines,
+    } => {
+      let mut procs = state
+        .procs
+        .iter()
+        .filter(|proc| selector.matches(&proc.name, &proc.tags));
+      match (procs.next(), procs.count()) {
+        (Some(proc), 0) => Ok(json!(proc.output_lines(*lines))),
+        (Some(_), others) => Err(CtlError::new(
+          CtlErrorCode::InvalidRequest,
+          format!(
+            "\"{}\" matches {} processes, expected one",
+            selector.0,
+            others + 1
+          ),
+        )),
+        (None, _) => Err(CtlError::new(
+          CtlErrorCode::NotFound,
+          format!("No process matches \"{}\"", selector.0),
+        )),
+      }
+    }
+    CtlQuery::GetConfig => Ok(config.to_json()),
+    // Handled by the server, see `handle_client`.
+    CtlQuery::Subscribe { .. } => Ok(Value::Null),
+  }
+}
+
+/// Remote commands fail instead of doing nothing when they target processes
+/// that don't exist.
+pub fn check_targets(event: &AppEvent, state: &State) -> Result<(), CtlError> {
+  if let AppEvent::Batch { cmds } = event {
+    return cmds.iter().try_for_each(|cmd| check_targets(cmd, state));
+  }
+  if let Some(Some(selector)) = event.proc_selector() {
+    if !state
+      .procs
+      .iter()
+      .any(|proc| selector.matches(&proc.name, &proc.tags))
+    {
+      return Err(CtlError::new(
+        CtlErrorCode::NotFound,
+        format!("No process matches \"{}\"", selector.0),
+      ));
+    }
+  }
+  // Running processes are not removed.
+  if let AppEvent::RemoveProc { id: None, proc } = event {
+    let running = match proc {
+      Some(selector) => {
+        let mut procs = state
+          .procs
+          .iter()
+          .filter(|proc| selector.matches(&proc.name, &proc.tags));
+        procs
+          .all(|proc| proc.is_up())
+          .then(|| format!("All processes matching \"{}\" are", selector.0))
+      }
+      None => state
+        .get_current_proc()
+        .filter(|proc| proc.is_up())
+        .map(|proc| format!("Process \"{}\" is", proc.name)),
+    };
+    if let Some(running) = running {
+      return Err(CtlError::new(
+        CtlErrorCode::InvalidRequest,
+        format!("{} running. Stop before removing.", running),
+      ));
+    }
+  }
+  Ok(())
+}
+
+fn proc_info(proc: &Proc) -> Value {
+  let exit_status = proc.exit_status.filter(|_| !proc.is_up());
+  json!({
+    "id": proc.id,
+    "name": proc.name,
+    "status": proc.status(),
+    "pid": proc.pid(),
+    "exit_code": exit_status.and_then(|status| status.code),
+    "signal": exit_status.and_then(|status| status.signal),
+    "uptime": proc.uptime().map(|uptime| uptime.as_secs_f64()),
+  })
+}
+
 pub async fn run_ctl(ctl: &str, config: &Config) -> anyhow::Result<()> {
-  let event: AppEvent = match serde_yaml::from_str(ctl) {
-    Ok(event) => event,
+  let cmd: CtlCommand = match serde_yaml::from_str(ctl) {
+    Ok(cmd) => cmd,
     Err(err) => {
-      let val: Value = serde_yaml::from_str(ctl)?;
+      let val: serde_yaml::Value = serde_yaml::from_str(ctl)?;
       println!(
         "Remote command parsed as:\n{}",
         serde_yaml::to_string(&val)?
@@ -18,12 +400,723 @@ pub async fn run_ctl(ctl: &str, config: &Config) -> anyhow::Result<()> {
     }
   };
 
-  let socket = match &config.server {
-    Some(ServerConfig::Tcp(addr)) => std::net::TcpStream::connect(addr)?,
-    None => anyhow::bail!("Server address is not defined."),
+  let socket = match (&config.server, project_server()) {
+    (Some(server), _) => connect(server).await?,
+    // Only an mprocs started in this directory creates the socket.
+    (None, Some(server)) => match connect(&server).await {
+      Ok(socket) => socket,
+      // A socket file left by an mprocs that didn't exit cleanly refuses
+      // connections.
+      Err(err)
+        if matches!(
+          err.kind(),
+          io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+        ) =>
+      {
+        bail!(
+          "No mprocs is running in this directory and no server is defined."
+        )
+      }
+      Err(err) => return Err(err.into()),
+    },
+    (None, None) => bail!("Server address is not defined."),
+  };
+  // Anyone can create the fallback directory in the temp dir, so don't send
+  // commands to a socket planted by another user.
+  #[cfg(not(windows))]
+  if let (None, Some(ServerConfig::Unix(path))) =
+    (&config.server, project_server())
+  {
+    if let Some(dir) = path.parent() {
+      check_private_dir(dir)?;
+    }
+  }
+  let (reader, mut writer) = tokio::io::split(socket);
+
+  let subscribe = matches!(cmd, CtlCommand::Query(CtlQuery::Subscribe { .. }));
+  let request = CtlRequest {
+    id: Some(Value::from(1)),
+    cmd,
   };
+  let mut buf = serde_json::to_vec(&request)?;
+  buf.push(b'\n');
+  writer.write_all(&buf).await?;
+
+  let mut reader = BufReader::new(reader);
+  let mut line = String::new();
+  reader.read_line(&mut line).await?;
+  if line.is_empty() {
+    anyhow::bail!("Connection closed without a response.");
+  }
+  let response: CtlResponse = serde_json::from_str(&line)?;
+  match response.into_result() {
+    Ok(_) if subscribe => (),
+    Ok(Value::Null) => println!("ok"),
+    Ok(result) => println!("{}", serde_json::to_string_pretty(&result)?),
+    Err(err) => anyhow::bail!("{}", err),
+  }
+
+  // Events are printed as they arrive until mprocs exits.
+  if subscribe {
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+      println!("{}", line);
+    }
+  }
+
+  Ok(())
+}
+
+/// The server used when none is configured: a socket for the current
+/// directory, so `--ctl` finds the mprocs started there.
+#[cfg(not(windows))]
+pub fn project_server() -> Option<ServerConfig> {
+  let cwd = std::env::current_dir().ok()?;
+  let dir = socket_dir(std::env::var_os("XDG_RUNTIME_DIR"));
+  Some(ServerConfig::Unix(project_socket(&dir, &cwd)))
+}
+
+/// `$XDG_RUNTIME_DIR/mprocs`, or `mprocs-<uid>` in the temp dir when
+/// `$XDG_RUNTIME_DIR` is not set.
+#[cfg(not(windows))]
+fn socket_dir(runtime_dir: Option<OsString>) -> PathBuf {
+  match runtime_dir.filter(|runtime_dir| !runtime_dir.is_empty()) {
+    Some(runtime_dir) => PathBuf::from(runtime_dir).join("mprocs"),
+    None => {
+      let uid = unsafe { libc::getuid() };
+      std::env::temp_dir().join(format!("mprocs-{}", uid))
+    }
+  }
+}
 
-  serde_yaml::to_writer(socket, &event).unwrap();
+#[cfg(not(windows))]
+fn project_socket(dir: &Path, cwd: &Path) -> PathBuf {
+  // FNV-1a, which unlike `DefaultHasher` doesn't change between builds.
+  let hash = cwd
+    .as_os_str()
+    .as_bytes()
+    .iter()
+    .fold(0xcbf29ce484222325u64, |hash, byte| {
+      (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+  dir.join(format!("{:016x}.sock", hash))
+}
+
+/// Creates a directory that only the user can access, or checks that an
+/// existing one is like that.
+#[cfg(not(windows))]
+fn create_private_dir(dir: &Path) -> anyhow::Result<()> {
+  match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+    // The umask may have removed bits.
+    Ok(()) => Ok(std::fs::set_permissions(
+      dir,
+      std::fs::Permissions::from_mode(0o700),
+    )?),
+    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+      check_private_dir(dir)
+    }
+    Err(err) => Err(err.into()),
+  }
+}
 
+#[cfg(not(windows))]
+fn check_private_dir(dir: &Path) -> anyhow::Result<()> {
+  let metadata = std::fs::symlink_metadata(dir)?;
+  if !metadata.is_dir() {
+    bail!("{} is not a directory", dir.display());
+  }
+  if metadata.uid() != unsafe { libc::getuid() } {
+    bail!("{} is owned by another user", dir.display());
+  }
+  if metadata.mode() & 0o777 != 0o700 {
+    bail!(
+      "{} must only be accessible by its owner (0700)",
+      dir.display()
+    );
+  }
   Ok(())
 }
+
+#[cfg(windows)]
+pub fn project_server() -> Option<ServerConfig> {
+  None
+}
+
+/// A connection to the server of either kind.
+trait CtlStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> CtlStream for S {}
+
+async fn connect(server: &ServerConfig) -> io::Result<Box<dyn CtlStream>> {
+  match server {
+    ServerConfig::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr).await?)),
+    #[cfg(not(windows))]
+    ServerConfig::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+  }
+}
+
+pub enum CtlListener {
+  Tcp(TcpListener),
+  #[cfg(not(windows))]
+  Unix(UnixSocket),
+}
+
+/// Removes the socket file when mprocs exits.
+#[cfg(not(windows))]
+pub struct UnixSocket {
+  listener: UnixListener,
+  path: PathBuf,
+}
+
+#[cfg(not(windows))]
+impl Drop for UnixSocket {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+impl CtlListener {
+  pub async fn bind(server: &ServerConfig) -> anyhow::Result<Self> {
+    match server {
+      ServerConfig::Tcp(addr) => {
+        Ok(CtlListener::Tcp(TcpListener::bind(addr).await?))
+      }
+      #[cfg(not(windows))]
+      ServerConfig::Unix(path) => Ok(CtlListener::Unix(bind_unix(path).await?)),
+    }
+  }
+
+  /// Listens on the `project_server` socket. mprocs works without it, so
+  /// failures, like another mprocs running in the same directory, are only
+  /// logged.
+  pub async fn bind_project() -> Option<Self> {
+    let server = project_server()?;
+    let result = async {
+      #[cfg(not(windows))]
+      if let ServerConfig::Unix(path) = &server {
+        if let Some(dir) = path.parent() {
+          create_private_dir(dir)?;
+        }
+      }
+      Self::bind(&server).await
+    }
+    .await;
+    match result {
+      Ok(listener) => Some(listener),
+      Err(err) => {
+        log::warn!("Failed to listen on {}: {}", server, err);
+        None
+      }
+    }
+  }
+
+  async fn accept(&self) -> io::Result<Box<dyn CtlStream>> {
+    match self {
+      CtlListener::Tcp(listener) => Ok(Box::new(listener.accept().await?.0)),
+      #[cfg(not(windows))]
+      CtlListener::Unix(socket) => {
+        Ok(Box::new(socket.listener.accept().await?.0))
+      }
+    }
+  }
+}
+
+/// Binds a socket that only the user can connect to. A socket file left by an
+/// mprocs that didn't exit cleanly is replaced.
+#[cfg(not(windows))]
+async fn bind_unix(path: &Path) -> anyhow::Result<UnixSocket> {
+  if let Ok(metadata) = std::fs::symlink_metadata(path) {
+    if !metadata.file_type().is_socket() {
+      bail!("{} exists and is not a socket", path.display());
+    }
+    if UnixStream::connect(path).await.is_ok() {
+      bail!("Another mprocs is listening on {}", path.display());
+    }
+    std::fs::remove_file(path)?;
+  }
+
+  // Bind in a new directory that only the user can access and move the socket
+  // into place after setting its permissions, so that nobody else can connect
+  // in between.
+  let name = match path.file_name() {
+    Some(name) => name,
+    None => bail!("{} is not a file path", path.display()),
+  };
+  let mut tmp_name = OsString::from(".");
+  tmp_name.push(name);
+  tmp_name.push(format!(".{}", std::process::id()));
+  let tmp_dir = path.with_file_name(tmp_name);
+  std::fs::DirBuilder::new().mode(0o700).create(&tmp_dir)?;
+  let tmp_path = tmp_dir.join("sock");
+  let result = (|| -> io::Result<_> {
+    let listener = UnixListener::bind(&tmp_path)?;
+    std::fs::set_permissions(
+      &tmp_path,
+      std::fs::Permissions::from_mode(0o600),
+    )?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(listener)
+  })();
+  let _ = std::fs::remove_dir_all(&tmp_dir);
+
+  Ok(UnixSocket {
+    listener: result?,
+    path: path.to_owned(),
+  })
+}
+
+/// Accepts clients until mprocs exits.
+pub async fn serve(
+  server: CtlListener,
+  ctl_tx: UnboundedSender<CtlMessage>,
+  events: EventSender,
+  exit_listener: triggered::Listener,
+) {
+  loop {
+    let on_exit = exit_listener.clone();
+    let socket = select! {
+      _ = on_exit.fuse() => break,
+      client = server.accept().fuse() => {
+        if let Ok(socket) = client {
+          socket
+        } else {
+          break;
+        }
+      }
+    };
+
+    tokio::spawn(handle_client(
+      socket,
+      ctl_tx.clone(),
+      events.clone(),
+      exit_listener.clone(),
+    ));
+  }
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite>(
+  socket: S,
+  ctl_tx: UnboundedSender<CtlMessage>,
+  events: EventSender,
+  exit_listener: triggered::Listener,
+) {
+  let (reader, mut writer) = tokio::io::split(socket);
+  let mut reader = BufReader::new(reader);
+  loop {
+    let on_exit = exit_listener.clone();
+    let line = select! {
+      _ = on_exit.fuse() => break,
+      line = read_line(&mut reader, MAX_REQUEST_LEN).fuse() => match line {
+        Ok(Some(line)) => line,
+        Ok(None) => break,
+        Err(err) => {
+          let error = CtlError::new(CtlErrorCode::ParseError, err.to_string());
+          write_line(&mut writer, &CtlResponse::new(None, Err(error))).await;
+          break;
+        }
+      },
+    };
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let mut subscription = None;
+    let response = match parse_request(&line) {
+      Ok(CtlRequest {
+        id,
+        cmd: CtlCommand::Query(CtlQuery::Subscribe { output, proc }),
+      }) => {
+        // Subscribe before responding, so that no later events are missed.
+        subscription = Some((events.subscribe(), output, proc));
+        CtlResponse::new(id, Ok(Value::Null))
+      }
+      Ok(request) => {
+        let (result_tx, result_rx) = oneshot::channel();
+        let result = match ctl_tx.send((request.cmd, result_tx)) {
+          Ok(()) => result_rx.await.unwrap_or_else(|_| Err(unavailable())),
+          Err(_) => Err(unavailable()),
+        };
+        CtlResponse::new(request.id, result)
+      }
+      Err(response) => response,
+    };
+    if !write_line(&mut writer, &response).await {
+      break;
+    }
+
+    if let Some((events, output, proc)) = subscription {
+      stream_events(
+        &mut reader,
+        &mut writer,
+        events,
+        output,
+        proc,
+        &exit_listener,
+      )
+      .await;
+      break;
+    }
+  }
+}
+
+/// Longest request line in bytes. Longer requests close the connection.
+const MAX_REQUEST_LEN: usize = 1024 * 1024;
+
+/// Reads a line without its line ending, or `None` at the end of the stream.
+async fn read_line<R: AsyncBufRead + Unpin>(
+  reader: &mut R,
+  max_len: usize,
+) -> io::Result<Option<String>> {
+  let mut buf = Vec::new();
+  // One more byte for the line ending.
+  (&mut *reader)
+    .take(max_len as u64 + 1)
+    .read_until(b'\n', &mut buf)
+    .await?;
+  if buf.is_empty() {
+    return Ok(None);
+  }
+  if buf.last() == Some(&b'\n') {
+    buf.pop();
+  } else if buf.len() > max_len {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("Request is longer than {} bytes", max_len),
+    ));
+  }
+  String::from_utf8(buf)
+    .map(Some)
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+/// Sends events until the client disconnects or mprocs exits. Anything else
+/// the client sends is ignored.
+async fn stream_events<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+  reader: &mut R,
+  writer: &mut W,
+  mut events: broadcast::Receiver<TaggedEvent>,
+  output: bool,
+  proc: Option<ProcSelector>,
+  exit_listener: &triggered::Listener,
+) {
+  // Without output events nothing is written for a long time, so writes
+  // can't tell that the client is gone.
+  let disconnected = async {
+    let mut buf = [0; 1024];
+    while let Ok(n) = reader.read(&mut buf).await {
+      if n == 0 {
+        break;
+      }
+    }
+  }
+  .fuse();
+  futures::pin_mut!(disconnected);
+
+  loop {
+    let on_exit = exit_listener.clone();
+    let TaggedEvent { event, tags } = select! {
+      _ = on_exit.fuse() => break,
+      _ = disconnected => break,
+      event = events.recv().fuse() => match event {
+        Ok(event) => event,
+        Err(RecvError::Lagged(skipped)) => TaggedEvent {
+          event: CtlEvent::Lagged { skipped },
+          tags: Vec::new(),
+        },
+        Err(RecvError::Closed) => break,
+      },
+    };
+
+    if matches!(event, CtlEvent::Output { .. }) && !output {
+      continue;
+    }
+    match (&proc, event.proc()) {
+      (Some(proc), Some(name)) if !proc.matches(name, &tags) => continue,
+      _ => (),
+    }
+    if !write_line(writer, &event).await {
+      break;
+    }
+  }
+}
+
+/// Writes a JSON line. Returns `false` if the client is gone.
+async fn write_line<W: AsyncWrite + Unpin>(
+  writer: &mut W,
+  value: &impl Serialize,
+) -> bool {
+  let mut buf = match serde_json::to_vec(value) {
+    Ok(buf) => buf,
+    Err(_) => return false,
+  };
+  buf.push(b'\n');
+  writer.write_all(&buf).await.is_ok()
+}
+
+/// Returns the error response for invalid requests.
+fn parse_request(line: &str) -> Result<CtlRequest, CtlResponse> {
+  let value: Value = serde_json::from_str(line).map_err(|err| {
+    let error = CtlError::new(CtlErrorCode::ParseError, err.to_string());
+    CtlResponse::new(None, Err(error))
+  })?;
+  let id = value.get("id").cloned();
+  serde_json::from_value(value).map_err(|err| {
+    let error = CtlError::new(CtlErrorCode::InvalidRequest, err.to_string());
+    CtlResponse::new(id, Err(error))
+  })
+}
+
+fn unavailable() -> CtlError {
+  CtlError::new(CtlErrorCode::Unavailable, "mprocs is shutting down")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_requests() {
+    let request = parse_request(r#"{"id": 3, "c": "restart-proc"}"#).unwrap();
+    assert_eq!(request.id, Some(Value::from(3)));
+    assert_eq!(
+      request.cmd,
+      CtlCommand::Event(AppEvent::RestartProc { proc: None })
+    );
+
+    let request = parse_request(r#"{"c": "add-proc", "cmd": "ls"}"#).unwrap();
+    assert_eq!(request.id, None);
+    assert_eq!(
+      request.cmd,
+      CtlCommand::Event(AppEvent::AddProc {
+        cmd: "ls".to_string()
+      })
+    );
+
+    let request =
+      parse_request(r#"{"c": "get-output", "proc": "api", "lines": 5}"#)
+        .unwrap();
+    assert_eq!(
+      request.cmd,
+      CtlCommand::Query(CtlQuery::GetOutput {
+        proc: ProcSelector("api".to_string()),
+        lines: Some(5)
+      })
+    );
+    let error = parse_request(r#"{"c": "get-output"}"#).unwrap_err();
+    let error = error.into_result().unwrap_err();
+    assert_eq!(error.code, CtlErrorCode::InvalidRequest);
+    assert_eq!(error.message, "missing field `proc`");
+
+    let error = parse_request(r#"{"id": "a", "c": "fly"}"#).unwrap_err();
+    assert_eq!(error.id, Some(Value::from("a")));
+    let error = error.into_result().unwrap_err();
+    assert_eq!(error.code, CtlErrorCode::InvalidRequest);
+
+    let error = parse_request("{c: quit}").unwrap_err();
+    assert_eq!(error.id, None);
+    let error = error.into_result().unwrap_err();
+    assert_eq!(error.code, CtlErrorCode::ParseError);
+
+    // Every query is told apart from `AppEvent`s by its name.
+    let queries = [
+      CtlQuery::ListProcs,
+      CtlQuery::GetOutput {
+        proc: ProcSelector("api".to_string()),
+        lines: None,
+      },
+      CtlQuery::GetConfig,
+      CtlQuery::Subscribe {
+        output: false,
+        proc: None,
+      },
+    ];
+    for query in queries {
+      let value = serde_json::to_value(&query).unwrap();
+      assert!(CtlQuery::NAMES.contains(&value["c"].as_str().unwrap()));
+    }
+  }
+
+  #[test]
+  fn output_events() {
+    let events = event_channel();
+    let tags = vec!["backend".to_string()];
+    let mut sink =
+      OutputEvents::new("api".to_string(), tags.clone(), events.clone());
+    // Nobody listens yet.
+    sink.write(b"zero\r\n");
+
+    let mut rx = events.subscribe();
+    sink.write(b"\x1b[32mone\x1b[0m\r\ntw");
+    sink.write(b"o\r\n");
+    let line = |line: &str| CtlEvent::Output {
+      proc: "api".to_string(),
+      line: line.to_string(),
+    };
+    let event = rx.try_recv().unwrap();
+    assert_eq!(event.event, line("one"));
+    assert_eq!(event.tags, tags);
+    assert_eq!(rx.try_recv().unwrap().event, line("two"));
+    assert!(rx.try_recv().is_err());
+
+    assert_eq!(
+      serde_json::to_string(&line("one")).unwrap(),
+      r#"{"event":"output","proc":"api","line":"one"}"#
+    );
+  }
+
+  #[test]
+  fn response_json() {
+    let ack = CtlResponse::new(Some(Value::from(1)), Ok(Value::Null));
+    assert_eq!(
+      serde_json::to_string(&ack).unwrap(),
+      r#"{"id":1,"result":null}"#
+    );
+    let ack: CtlResponse = serde_json::from_str(r#"{"result":null}"#).unwrap();
+    assert_eq!(ack.into_result().unwrap(), Value::Null);
+
+    let error = CtlError::new(CtlErrorCode::Unavailable, "closed");
+    let response = CtlResponse::new(None, Err(error));
+    let json = serde_json::to_string(&response).unwrap();
+    assert_eq!(
+      json,
+      r#"{"error":{"code":"unavailable","message":"closed"}}"#
+    );
+    let response: CtlResponse = serde_json::from_str(&json).unwrap();
+    let error = response.into_result().unwrap_err();
+    assert_eq!(error.to_string(), "unavailable: closed");
+  }
+
+  #[tokio::test]
+  async fn subscriber_disconnects() {
+    let (client, server) = tokio::io::duplex(1024);
+    let (ctl_tx, _ctl_rx) = tokio::sync::mpsc::unbounded_channel();
+    let events = event_channel();
+    let (_exit_trigger, exit_listener) = triggered::trigger();
+    let handler = tokio::spawn(handle_client(
+      server,
+      ctl_tx,
+      events.clone(),
+      exit_listener,
+    ));
+
+    let (reader, mut writer) = tokio::io::split(client);
+    writer.write_all(b"{\"c\": \"subscribe\"}\n").await.unwrap();
+    let mut lines = BufReader::new(reader).lines();
+    let response = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(response, r#"{"result":null}"#);
+    assert_eq!(events.receiver_count(), 1);
+
+    // No events are sent, but the subscription still ends.
+    drop((lines, writer));
+    let timeout = std::time::Duration::from_secs(5);
+    tokio::time::timeout(timeout, handler)
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(events.receiver_count(), 0);
+  }
+
+  #[tokio::test]
+  async fn subscribe_by_tag() {
+    let (client, server) = tokio::io::duplex(1024);
+    let (ctl_tx, _ctl_rx) = tokio::sync::mpsc::unbounded_channel();
+    let events = event_channel();
+    let (_exit_trigger, exit_listener) = triggered::trigger();
+    tokio::spawn(handle_client(server, ctl_tx, events.clone(), exit_listener));
+
+    let (reader, mut writer) = tokio::io::split(client);
+    let request = b"{\"c\": \"subscribe\", \"proc\": \"tag:backend\"}\n";
+    writer.write_all(request).await.unwrap();
+    let mut lines = BufReader::new(reader).lines();
+    let response = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(response, r#"{"result":null}"#);
+
+    let started = |proc: &str| CtlEvent::Started {
+      proc: proc.to_string(),
+    };
+    send_event(&events, started("web"), &["frontend".to_string()]);
+    send_event(&events, started("api"), &["backend".to_string()]);
+    let event = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(event, r#"{"event":"started","proc":"api"}"#);
+  }
+
+  #[tokio::test]
+  async fn request_lines() {
+    let mut reader: &[u8] = b"{}\n0123456789\n{\"c\": \"quit\"}";
+    assert_eq!(read_line(&mut reader, 10).await.unwrap().unwrap(), "{}");
+    assert_eq!(
+      read_line(&mut reader, 10).await.unwrap().unwrap(),
+      "0123456789"
+    );
+    let err = read_line(&mut reader, 10).await.unwrap_err();
+    assert_eq!(err.to_string(), "Request is longer than 10 bytes");
+
+    let mut reader: &[u8] = b"{\"c\": \"quit\"}";
+    assert_eq!(
+      read_line(&mut reader, 100).await.unwrap().unwrap(),
+      "{\"c\": \"quit\"}"
+    );
+    assert!(read_line(&mut reader, 100).await.unwrap().is_none());
+  }
+
+  #[cfg(not(windows))]
+  #[tokio::test]
+  async fn unix_socket() {
+    let path = std::env::temp_dir()
+      .join(format!("mprocs-test-{}.sock", std::process::id()));
+    let server = ServerConfig::Unix(path.clone());
+
+    // A socket file without a listener is replaced.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    let listener = CtlListener::bind(&server).await.unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(connect(&server).await.is_ok());
+    let err = CtlListener::bind(&server).await.err().unwrap();
+    assert!(err.to_string().starts_with("Another mprocs is listening"));
+
+    drop(listener);
+    assert!(!path.exists());
+    let tmp_dir = path.with_file_name(format!(
+      ".{}.{}",
+      path.file_name().unwrap().to_str().unwrap(),
+      std::process::id()
+    ));
+    assert!(!tmp_dir.exists());
+  }
+
+  #[cfg(not(windows))]
+  #[test]
+  fn project_socket_dir() {
+    assert_eq!(
+      socket_dir(Some(OsString::from("/run/user/1000"))),
+      Path::new("/run/user/1000/mprocs")
+    );
+    let uid = unsafe { libc::getuid() };
+    let fallback = std::env::temp_dir().join(format!("mprocs-{}", uid));
+    assert_eq!(socket_dir(None), fallback);
+    assert_eq!(socket_dir(Some(OsString::new())), fallback);
+    assert_eq!(
+      project_socket(Path::new("/run/mprocs"), Path::new("/home/a/app")),
+      project_socket(Path::new("/run/mprocs"), Path::new("/home/a/app")),
+    );
+    assert_ne!(
+      project_socket(Path::new("/run/mprocs"), Path::new("/home/a/app")),
+      project_socket(Path::new("/run/mprocs"), Path::new("/home/a/api")),
+    );
+
+    let dir = std::env::temp_dir()
+      .join(format!("mprocs-test-{}.dir", std::process::id()));
+    create_private_dir(&dir).unwrap();
+    let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+    create_private_dir(&dir).unwrap();
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))
+      .unwrap();
+    let err = create_private_dir(&dir).err().unwrap();
+    assert!(err
+      .to_string()
+      .ends_with("must only be accessible by its owner (0700)"));
+    std::fs::remove_dir(&dir).unwrap();
+  }
+}
@@ -0,0 +1,159 @@
+use crate::interpolate::interpolate;
+
+/// Parses a dotenv file. Supports comments, an `export` prefix, single
+/// quoted (literal) and double quoted (with escapes) values spanning
+/// multiple lines, and `${NAME}` expansion in unquoted and double quoted
+/// values. Undefined variables expand to an empty string.
+pub fn parse_dotenv(
+  src: &str,
+  lookup: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>, String> {
+  let mut vars: Vec<(String, String)> = Vec::new();
+  let mut pos = 0;
+  let mut line_no = 0;
+
+  while pos < src.len() {
+    line_no += 1;
+    let start_line = line_no;
+    let line_end = src[pos..].find('\n').map_or(src.len(), |i| pos + i);
+    let line = &src[pos..line_end];
+    pos = (line_end + 1).min(src.len());
+
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+    let (key, value) = line
+      .split_once('=')
+      .ok_or_else(|| format!("Expected KEY=VALUE on line {}", start_line))?;
+    let key = key.trim();
+    if key.is_empty()
+      || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+      return Err(format!("Invalid name \"{}\" on line {}", key, start_line));
+    }
+    let value = value.trim_start();
+
+    let expand = |s: &str| {
+      interpolate(s, |name| {
+        vars
+          .iter()
+          .rev()
+          .find(|(k, _)| k == name)
+          .map(|(_, v)| v.clone())
+          .or_else(|| lookup(name))
+          .or_else(|| Some(String::new()))
+      })
+      .map_err(|msg| format!("{} on line {}", msg, start_line))
+    };
+
+    let value = match value.chars().next() {
+      Some(quote @ ('\'' | '"')) => {
+        // Quoted values may continue on the following lines, so they are
+        // read from the source rather than from the current line.
+        let body_start = value.as_ptr() as usize - src.as_ptr() as usize + 1;
+        let (raw, consumed) = read_quoted(&src[body_start..], quote)
+          .ok_or_else(|| format!("Unclosed quote on line {}", start_line))?;
+        let end = body_start + consumed;
+        pos = src[end..].find('\n').map_or(src.len(), |i| end + i + 1);
+        line_no += raw.matches('\n').count();
+        if quote == '"' {
+          expand(&unescape(&raw))?
+        } else {
+          raw
+        }
+      }
+      _ => {
+        let value = match value.find(" #") {
+          Some(pos) => &value[..pos],
+          None => value,
+        };
+        expand(value.trim_end())?
+      }
+    };
+
+    vars.push((key.to_string(), value));
+  }
+
+  Ok(vars)
+}
+
+/// Reads until the closing quote. Returns the raw contents and the number of
+/// bytes consumed including the quote.
+fn read_quoted(s: &str, quote: char) -> Option<(String, usize)> {
+  let mut out = String::new();
+  let mut chars = s.char_indices();
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '\\' if quote == '"' => {
+        out.push(c);
+        if let Some((_, c)) = chars.next() {
+          out.push(c);
+        }
+      }
+      c if c == quote => return Some((out, i + c.len_utf8())),
+      c => out.push(c),
+    }
+  }
+  None
+}
+
+fn unescape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      out.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => out.push('\n'),
+      Some('r') => out.push('\r'),
+      Some('t') => out.push('\t'),
+      // `interpolate` reads `$${` as a literal `${`.
+      Some('$') if chars.clone().next() == Some('{') => out.push_str("$$"),
+      Some(c) => out.push(c),
+      None => out.push('\\'),
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dotenv() {
+    let src = "# comment\n\
+      export A=1\n\
+      B = two words # comment\n\
+      C='${A} literal'\n\
+      D=\"line1\\nline2 ${A} \\${A} \\$A\"\n\
+      E=\"multi\n\
+      line\"\n\
+      F=${HOME_X:-none}/${B}\n";
+    let vars = parse_dotenv(src, |_| None).unwrap();
+    let vars = vars
+      .iter()
+      .map(|(k, v)| (k.as_str(), v.as_str()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      vars,
+      vec![
+        ("A", "1"),
+        ("B", "two words"),
+        ("C", "${A} literal"),
+        ("D", "line1\nline2 1 ${A} $A"),
+        ("E", "multi\nline"),
+        ("F", "none/two words"),
+      ]
+    );
+
+    assert_eq!(
+      parse_dotenv("A=1\nB\n", |_| None).unwrap_err(),
+      "Expected KEY=VALUE on line 2"
+    );
+  }
+}
@@ -2,12 +2,14 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
-use crate::key::Key;
+use crate::{key::Key, selector::ProcSelector};
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 #[serde(tag = "c", rename_all = "kebab-case")]
 pub enum AppEvent {
-  Batch { cmds: Vec<AppEvent> },
+  Batch {
+    cmds: Vec<AppEvent>,
+  },
 
   QuitOrAsk,
   Quit,
@@ -20,29 +22,74 @@ pub enum AppEvent {
 
   NextProc,
   PrevProc,
-  SelectProc { index: usize },
-  StartProc,
-  TermProc,
-  KillProc,
-  RestartProc,
-  ForceRestartProc,
+  SelectProc {
+    index: usize,
+  },
+  // Commands with a `proc` selector act on the selected process without one.
+  StartProc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
+  TermProc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
+  KillProc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
+  RestartProc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
+  ForceRestartProc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
   ShowAddProc,
-  AddProc { cmd: String },
+  AddProc {
+    cmd: String,
+  },
   ShowRemoveProc,
-  RemoveProc { id: usize },
+  RemoveProc {
+    /// Set by the remove dialog. `id` of remote requests is the request id.
+    #[serde(skip)]
+    id: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
 
-  ScrollDownLines { n: usize },
-  ScrollUpLines { n: usize },
+  ScrollDownLines {
+    n: usize,
+  },
+  ScrollUpLines {
+    n: usize,
+  },
   ScrollDown,
   ScrollUp,
 
   CopyModeEnter,
   CopyModeLeave,
-  CopyModeMove { dir: CopyMove },
+  CopyModeMove {
+    dir: CopyMove,
+  },
   CopyModeEnd,
   CopyModeCopy,
 
-  SendKey { key: Key },
+  SearchStart,
+  SearchNext,
+  SearchPrev,
+
+  ReloadConfig,
+  SwitchProfile {
+    name: String,
+  },
+
+  SendKey {
+    key: Key,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proc: Option<ProcSelector>,
+  },
 }
 
 impl AppEvent {
@@ -59,15 +106,20 @@ impl AppEvent {
       AppEvent::NextProc => "Next".to_string(),
       AppEvent::PrevProc => "Prev".to_string(),
       AppEvent::SelectProc { index } => format!("Select process #{}", index),
-      AppEvent::StartProc => "Start".to_string(),
-      AppEvent::TermProc => "Stop".to_string(),
-      AppEvent::KillProc => "Kill".to_string(),
-      AppEvent::RestartProc => "Restart".to_string(),
-      AppEvent::ForceRestartProc => "Force restart".to_string(),
+      AppEvent::StartProc { proc } => with_target("Start", proc),
+      AppEvent::TermProc { proc } => with_target("Stop", proc),
+      AppEvent::KillProc { proc } => with_target("Kill", proc),
+      AppEvent::RestartProc { proc } => with_target("Restart", proc),
+      AppEvent::ForceRestartProc { proc } => with_target("Force restart", proc),
       AppEvent::ShowAddProc => "New process dialog".to_string(),
       AppEvent::AddProc { cmd } => format!("New process `{}`", cmd),
       AppEvent::ShowRemoveProc => "Remove process dialog".to_string(),
-      AppEvent::RemoveProc { id } => format!("Remove process by id {}", id),
+      AppEvent::RemoveProc { id: Some(id), .. } => {
+        format!("Remove process by id {}", id)
+      }
+      AppEvent::RemoveProc { id: None, proc } => {
+        with_target("Remove process", proc)
+      }
       AppEvent::ScrollDownLines { n } => {
         format!("Scroll down {} {}", n, lines_str(*n))
       }
@@ -83,11 +135,97 @@ impl AppEvent {
       }
       AppEvent::CopyModeEnd => "Select end position".to_string(),
       AppEvent::CopyModeCopy => "Copy selected text".to_string(),
-      AppEvent::SendKey { key } => format!("Send {} key", key.to_string()),
+      AppEvent::SearchStart => "Search".to_string(),
+      AppEvent::SearchNext => "Next match".to_string(),
+      AppEvent::SearchPrev => "Previous match".to_string(),
+      AppEvent::ReloadConfig => "Reload config".to_string(),
+      AppEvent::SwitchProfile { name } => {
+        format!("Switch to profile `{}`", name)
+      }
+      AppEvent::SendKey { key, proc } => {
+        with_target(&format!("Send {} key", key), proc)
+      }
+    }
+  }
+
+  /// The `proc` argument of commands that act on processes.
+  pub fn proc_selector(&self) -> Option<&Option<ProcSelector>> {
+    match self {
+      AppEvent::StartProc { proc }
+      | AppEvent::TermProc { proc }
+      | AppEvent::KillProc { proc }
+      | AppEvent::RestartProc { proc }
+      | AppEvent::ForceRestartProc { proc }
+      | AppEvent::RemoveProc { proc, .. }
+      | AppEvent::SendKey { proc, .. } => Some(proc),
+      _ => None,
     }
   }
 }
 
+fn with_target(desc: &str, proc: &Option<ProcSelector>) -> String {
+  match proc {
+    Some(proc) => format!("{} `{}`", desc, proc.0),
+    None => desc.to_string(),
+  }
+}
+
+impl AppEvent {
+  /// One instance of every command, with placeholder arguments. Used to
+  /// generate the config schema, where `proc` is optional.
+  pub fn all() -> Vec<AppEvent> {
+    vec![
+      AppEvent::Batch { cmds: Vec::new() },
+      AppEvent::QuitOrAsk,
+      AppEvent::Quit,
+      AppEvent::ForceQuit,
+      AppEvent::ToggleFocus,
+      AppEvent::FocusProcs,
+      AppEvent::FocusTerm,
+      AppEvent::Zoom,
+      AppEvent::NextProc,
+      AppEvent::PrevProc,
+      AppEvent::SelectProc { index: 0 },
+      AppEvent::StartProc { proc: selector() },
+      AppEvent::TermProc { proc: selector() },
+      AppEvent::KillProc { proc: selector() },
+      AppEvent::RestartProc { proc: selector() },
+      AppEvent::ForceRestartProc { proc: selector() },
+      AppEvent::ShowAddProc,
+      AppEvent::AddProc { cmd: String::new() },
+      AppEvent::ShowRemoveProc,
+      AppEvent::RemoveProc {
+        id: None,
+        proc: selector(),
+      },
+      AppEvent::ScrollDownLines { n: 0 },
+      AppEvent::ScrollUpLines { n: 0 },
+      AppEvent::ScrollDown,
+      AppEvent::ScrollUp,
+      AppEvent::CopyModeEnter,
+      AppEvent::CopyModeLeave,
+      AppEvent::CopyModeMove { dir: CopyMove::Up },
+      AppEvent::CopyModeEnd,
+      AppEvent::CopyModeCopy,
+      AppEvent::SearchStart,
+      AppEvent::SearchNext,
+      AppEvent::SearchPrev,
+      AppEvent::ReloadConfig,
+      AppEvent::SwitchProfile {
+        name: String::new(),
+      },
+      AppEvent::SendKey {
+        key: Key::parse("<C-a>").unwrap(),
+        proc: selector(),
+      },
+    ]
+  }
+}
+
+fn selector() -> Option<ProcSelector> {
+  Some(ProcSelector(String::new()))
+}
+
 fn lines_str(n: usize) -> &'static str {
   if n == 1 {
     "line"
@@ -104,6 +242,15 @@ pub enum CopyMove {
   Down,
 }
 
+impl CopyMove {
+  pub const ALL: [CopyMove; 4] = [
+    CopyMove::Up,
+    CopyMove::Right,
+    CopyMove::Left,
+    CopyMove::Down,
+  ];
+}
+
 impl Display for CopyMove {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let str = match self {
@@ -129,10 +276,64 @@ mod tests {
 
     assert_eq!(
       serde_yaml::to_string(&AppEvent::SendKey {
-        key: Key::parse("<c-a>").unwrap()
+        key: Key::parse("<c-a>").unwrap(),
+        proc: None,
       })
       .unwrap(),
       "---\nc: send-key\nkey: \"<C-a>\"\n"
     );
+    assert_eq!(
+      serde_yaml::from_str::<AppEvent>("{c: restart-proc, proc: api}").unwrap(),
+      AppEvent::RestartProc {
+        proc: Some(ProcSelector("api".to_string()))
+      }
+    );
+  }
+
+  #[test]
+  fn all_events() {
+    // Stops compiling when a variant is added. Add it to `AppEvent::all` too,
+    // in the same place.
+    fn index(event: &AppEvent) -> usize {
+      match event {
+        AppEvent::Batch { .. } => 0,
+        AppEvent::QuitOrAsk => 1,
+        AppEvent::Quit => 2,
+        AppEvent::ForceQuit => 3,
+        AppEvent::ToggleFocus => 4,
+        AppEvent::FocusProcs => 5,
+        AppEvent::FocusTerm => 6,
+        AppEvent::Zoom => 7,
+        AppEvent::NextProc => 8,
+        AppEvent::PrevProc => 9,
+        AppEvent::SelectProc { .. } => 10,
+        AppEvent::StartProc { .. } => 11,
+        AppEvent::TermProc { .. } => 12,
+        AppEvent::KillProc { .. } => 13,
+        AppEvent::RestartProc { .. } => 14,
+        AppEvent::ForceRestartProc { .. } => 15,
+        AppEvent::ShowAddProc => 16,
+        AppEvent::AddProc { .. } => 17,
+        AppEvent::ShowRemoveProc => 18,
+        AppEvent::RemoveProc { .. } => 19,
+        AppEvent::ScrollDownLines { .. } => 20,
+        AppEvent::ScrollUpLines { .. } => 21,
+        AppEvent::ScrollDown => 22,
+        AppEvent::ScrollUp => 23,
+        AppEvent::CopyModeEnter => 24,
+        AppEvent::CopyModeLeave => 25,
+        AppEvent::CopyModeMove { .. } => 26,
+        AppEvent::CopyModeEnd => 27,
+        AppEvent::CopyModeCopy => 28,
+        AppEvent::SearchStart => 29,
+        AppEvent::SearchNext => 30,
+        AppEvent::SearchPrev => 31,
+        AppEvent::ReloadConfig => 32,
+        AppEvent::SwitchProfile { .. } => 33,
+        AppEvent::SendKey { .. } => 34,
+      }
+    }
+    let indexes = AppEvent::all().iter().map(index).collect::<Vec<_>>();
+    assert_eq!(indexes, (0..indexes.len()).collect::<Vec<_>>());
   }
 }
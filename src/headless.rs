@@ -0,0 +1,389 @@
+//! Runs processes without the terminal UI. Output of every process is
+//! printed line by line with a `[name]` prefix. Used with `--headless`.
+//! Remote commands that act on processes work like with the UI.
+
+use std::{
+  io::Write,
+  sync::{Arc, Mutex},
+};
+
+use crossterm::tty::IsTty;
+use futures::{future::FutureExt, select};
+use serde_json::Value;
+use tokio::sync::mpsc::unbounded_channel;
+use tui::layout::Rect;
+
+use crate::{
+  config::Config,
+  ctl::{
+    check_targets, event_channel, run_query, send_event, serve, CtlCommand,
+    CtlError, CtlErrorCode, CtlEvent, CtlListener, CtlMessage, EventSender,
+    OutputEvents,
+  },
+  event::AppEvent,
+  proc::{ExitStatus, OutputSink, Proc, ProcState, ProcUpdate},
+  state::{Scope, State},
+  strip_ansi::AnsiStripper,
+};
+
+/// Cyan, yellow, green, magenta, blue and red.
+const PREFIX_COLORS: [u8; 6] = [36, 33, 32, 35, 34, 31];
+
+fn stdout_is_tty() -> bool {
+  std::io::stdout().is_tty()
+}
+
+/// Runs the processes until all of them are down. Returns the exit code set
+/// by `on_exit` policies, or 0 if every process that was started exited
+/// successfully and 1 otherwise.
+pub async fn run_headless(config: Config) -> anyhow::Result<i32> {
+  let is_tty = stdout_is_tty();
+  let color = is_tty && std::env::var_os("NO_COLOR").is_none();
+  let (cols, rows) = crossterm::terminal::size().unwrap_or((120, 40));
+  let size = Rect::new(0, 0, cols, rows);
+  let name_width = config
+    .procs
+    .iter()
+    .map(|cfg| cfg.name.chars().count())
+    .max()
+    .unwrap_or(0);
+
+  let (upd_tx, mut upd_rx) = unbounded_channel::<(usize, ProcUpdate)>();
+  let (ctl_tx, mut ctl_rx) = unbounded_channel::<CtlMessage>();
+  let events = event_channel();
+  let mut state = State {
+    scope: Scope::Procs,
+    procs: Vec::new(),
+    selected: 0,
+    modal: None,
+    quitting: false,
+    exit_code: None,
+  };
+  let mut printers = Vec::new();
+  for (i, cfg) in config.procs.iter().enumerate() {
+    let printer = Arc::new(Mutex::new(LinePrinter::new(
+      &cfg.name,
+      name_width,
+      color.then(|| PREFIX_COLORS[i % PREFIX_COLORS.len()]),
+      !is_tty,
+    )));
+    let output =
+      OutputEvents::new(cfg.name.clone(), cfg.tags.clone(), events.clone());
+    let proc = Proc::with_output(
+      cfg.name.clone(),
+      cfg,
+      upd_tx.clone(),
+      size,
+      vec![printer.clone(), Arc::new(Mutex::new(output))],
+    );
+    report_spawn_error(&proc, &printer);
+    printers.push(printer);
+    state.procs.push(proc);
+  }
+  state.start_waiting_procs();
+
+  let (exit_trigger, exit_listener) = triggered::trigger();
+  let server = match &config.server {
+    Some(server) => Some(CtlListener::bind(server).await?),
+    None => CtlListener::bind_project().await,
+  };
+  let server_thread = server.map(|server| {
+    tokio::spawn(serve(server, ctl_tx.clone(), events.clone(), exit_listener))
+  });
+
+  let mut interrupted = false;
+  let mut ctrl_c = Box::pin(tokio::signal::ctrl_c().fuse());
+  while !state.procs.iter().all(is_settled) {
+    select! {
+      upd = upd_rx.recv().fuse() => {
+        let (id, upd) = match upd {
+          Some(upd) => upd,
+          None => break,
+        };
+        let index = match state.procs.iter().position(|p| p.id == id) {
+          Some(index) => index,
+          None => continue,
+        };
+        let printer = &printers[index];
+        handle_proc_update(&mut state, &config, index, upd, printer, &events);
+      }
+      msg = ctl_rx.recv().fuse() => {
+        if let Some((cmd, result_tx)) = msg {
+          let result = match cmd {
+            CtlCommand::Event(event) => check_targets(&event, &state)
+              .and_then(|()| handle_event(&mut state, &event))
+              .map(|()| Value::Null),
+            CtlCommand::Query(query) => run_query(&query, &state, &config),
+          };
+          let _ = result_tx.send(result);
+        }
+      }
+      _ = ctrl_c => {
+        // The first interrupt stops processes gracefully, the second one
+        // kills them.
+        state.quitting = true;
+        for proc in &mut state.procs {
+          if interrupted {
+            proc.kill();
+          } else {
+            proc.stop_or_cancel();
+          }
+        }
+        interrupted = true;
+        ctrl_c.set(tokio::signal::ctrl_c().fuse());
+      }
+    }
+  }
+
+  exit_trigger.trigger();
+  if let Some(server_thread) = server_thread {
+    let _ = server_thread.await;
+  }
+
+  let mut not_started = false;
+  for (proc, printer) in state.procs.iter().zip(&printers) {
+    if proc.waiting_for_deps {
+      print_message(printer, "not started: dependencies are not ready");
+      not_started = true;
+    }
+  }
+
+  Ok(match state.exit_code {
+    Some(code) => code,
+    None if interrupted || not_started => 1,
+    None => state.combined_exit_code(),
+  })
+}
+
+/// Remote commands without the UI. Only commands that act on processes and
+/// quitting are available, and they need a `proc` selector since there is no
+/// selected process.
+fn handle_event(state: &mut State, event: &AppEvent) -> Result<(), CtlError> {
+  match event {
+    AppEvent::Batch { cmds } => {
+      cmds.iter().try_for_each(|cmd| handle_event(state, cmd))
+    }
+    AppEvent::Quit | AppEvent::QuitOrAsk => {
+      state.quitting = true;
+      for proc in &mut state.procs {
+        proc.stop_or_cancel();
+      }
+      Ok(())
+    }
+    AppEvent::ForceQuit => {
+      state.quitting = true;
+      for proc in &mut state.procs {
+        proc.kill();
+      }
+      Ok(())
+    }
+    AppEvent::RemoveProc { .. } => Err(unavailable(event)),
+    _ => match event.proc_selector() {
+      Some(Some(_)) => {
+        state.handle_proc_event(event);
+        Ok(())
+      }
+      Some(None) => Err(CtlError::new(
+        CtlErrorCode::InvalidRequest,
+        "A \"proc\" selector is required in headless mode",
+      )),
+      None => Err(unavailable(event)),
+    },
+  }
+}
+
+fn unavailable(event: &AppEvent) -> CtlError {
+  let value = serde_json::to_value(event).unwrap_or_default();
+  CtlError::new(
+    CtlErrorCode::InvalidRequest,
+    format!(
+      "\"{}\" is not available in headless mode",
+      value["c"].as_str().unwrap_or_default()
+    ),
+  )
+}
+
+fn handle_proc_update(
+  state: &mut State,
+  config: &Config,
+  index: usize,
+  upd: ProcUpdate,
+  printer: &Mutex<LinePrinter>,
+  events: &EventSender,
+) {
+  let quitting = state.quitting;
+  let proc = &mut state.procs[index];
+  match upd {
+    ProcUpdate::Render => (),
+    ProcUpdate::Stopped(status) => {
+      proc.exit_status = Some(status);
+      print_message(printer, &describe_exit(status));
+      let stopped = CtlEvent::Stopped {
+        proc: proc.name.clone(),
+        exit_code: status.code,
+        signal: status.signal,
+      };
+      send_event(events, stopped, &proc.tags);
+      if proc.to_restart {
+        proc.start();
+        proc.to_restart = false;
+      } else if !quitting {
+        proc.schedule_auto_restart(status.success());
+      }
+      state.apply_exit_policy(&config.on_exit, index);
+    }
+    ProcUpdate::Started => {
+      report_spawn_error(proc, printer);
+      if proc.is_up() {
+        let event = CtlEvent::Started {
+          proc: proc.name.clone(),
+        };
+        send_event(events, event, &proc.tags);
+      }
+      state.start_waiting_procs();
+    }
+    ProcUpdate::Ready => {
+      if proc.is_up() {
+        proc.ready = true;
+        let event = CtlEvent::Ready {
+          proc: proc.name.clone(),
+        };
+        send_event(events, event, &proc.tags);
+      }
+      state.start_waiting_procs();
+    }
+    ProcUpdate::StopTimer => proc.check_stop_timeout(),
+    ProcUpdate::FilesChanged => {
+      if !quitting {
+        proc.restart_on_change();
+      }
+    }
+    ProcUpdate::AutoRestart => {
+      if !quitting {
+        proc.auto_restart();
+      }
+    }
+  }
+}
+
+/// The process is down for good: it has exited (or was never started) and
+/// no restart is pending.
+fn is_settled(proc: &Proc) -> bool {
+  let exited = match proc.inst {
+    ProcState::Some(_) => proc.exit_status.is_some(),
+    ProcState::None | ProcState::Error(_) => true,
+  };
+  exited && !proc.restart_pending() && !proc.to_restart
+}
+
+fn report_spawn_error(proc: &Proc, printer: &Mutex<LinePrinter>) {
+  if let ProcState::Error(err) = &proc.inst {
+    print_message(printer, &format!("failed to start: {}", err));
+  }
+}
+
+fn describe_exit(status: ExitStatus) -> String {
+  match status {
+    ExitStatus {
+      code: Some(code), ..
+    } => format!("exited with code {}", code),
+    ExitStatus {
+      signal: Some(signal),
+      ..
+    } => format!("killed by signal {}", signal),
+    _ => "exited".to_string(),
+  }
+}
+
+fn print_message(printer: &Mutex<LinePrinter>, msg: &str) {
+  if let Ok(mut printer) = printer.lock() {
+    printer.message(msg);
+  }
+}
+
+/// Prefixes complete lines of a process output. A partial line is kept until
+/// its end arrives or the process exits.
+struct LinePrinter {
+  prefix: String,
+  color: bool,
+  stripper: Option<AnsiStripper>,
+  buf: Vec<u8>,
+}
+
+impl LinePrinter {
+  fn new(name: &str, width: usize, color: Option<u8>, strip: bool) -> Self {
+    let label = format!("{:<width$}", format!("[{}]", name), width = width + 2);
+    let prefix = match color {
+      Some(color) => format!("\x1b[{}m{}\x1b[0m ", color, label),
+      None => format!("{} ", label),
+    };
+    LinePrinter {
+      prefix,
+      color: color.is_some(),
+      stripper: strip.then(AnsiStripper::new),
+      buf: Vec::new(),
+    }
+  }
+
+  fn take_line(&mut self) -> Option<Vec<u8>> {
+    let pos = self.buf.iter().position(|b| *b == b'\n')?;
+    let rest = self.buf.split_off(pos + 1);
+    let mut line = std::mem::replace(&mut self.buf, rest);
+    line.pop();
+    if line.last() == Some(&b'\r') {
+      line.pop();
+    }
+    Some(line)
+  }
+
+  fn print_line(&self, line: &[u8]) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(self.prefix.as_bytes());
+    let _ = stdout.write_all(line);
+    // Colors left on by the process must not leak into the next prefix.
+    if self.color {
+      let _ = stdout.write_all(b"\x1b[0m");
+    }
+    let _ = stdout.write_all(b"\n");
+    let _ = stdout.flush();
+  }
+
+  /// Prints a line from mprocs itself after any pending output.
+  fn message(&mut self, msg: &str) {
+    if !self.buf.is_empty() {
+      let line = std::mem::take(&mut self.buf);
+      self.print_line(&line);
+    }
+    self.print_line(msg.as_bytes());
+  }
+}
+
+impl OutputSink for LinePrinter {
+  fn write(&mut self, bytes: &[u8]) {
+    match &mut self.stripper {
+      Some(stripper) => stripper.strip_into(bytes, &mut self.buf),
+      None => self.buf.extend_from_slice(bytes),
+    }
+    while let Some(line) = self.take_line() {
+      self.print_line(&line);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn splits_lines() {
+    let mut printer = LinePrinter::new("web", 5, None, true);
+    assert_eq!(printer.prefix, "[web]   ");
+
+    printer.buf.extend_from_slice(b"one\r\ntw");
+    assert_eq!(printer.take_line(), Some(b"one".to_vec()));
+    assert_eq!(printer.take_line(), None);
+    printer.buf.extend_from_slice(b"o\n");
+    assert_eq!(printer.take_line(), Some(b"two".to_vec()));
+    assert!(printer.buf.is_empty());
+  }
+}
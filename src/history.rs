@@ -0,0 +1,396 @@
+//! Complete output of processes with `scrollback: unlimited`. The output is
+//! kept in lz4 compressed blocks. The terminal of a process only holds the
+//! recent part; older output is shown by replaying the block that contains
+//! it into a separate terminal.
+
+use std::sync::{Arc, RwLock};
+
+use crate::{
+  proc::{scrollback_rows, OutputSink, VtWrap},
+  strip_ansi::AnsiStripper,
+};
+
+/// Uncompressed size of a block. Blocks end at a line break where possible.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+struct Block {
+  /// Output compressed with its size prepended.
+  data: Vec<u8>,
+  /// Number of line breaks before the block.
+  first_line: usize,
+}
+
+#[derive(Default)]
+pub struct OutputArchive {
+  blocks: Vec<Block>,
+  /// Output after the last block, not compressed yet.
+  current: Vec<u8>,
+  /// Number of line breaks before `current`.
+  current_first_line: usize,
+  /// Number of line breaks in all output.
+  lines: usize,
+}
+
+impl OutputArchive {
+  pub fn lines(&self) -> usize {
+    self.lines
+  }
+
+  /// Number of blocks with lines before `end_line`.
+  pub fn blocks_before(&self, end_line: usize) -> usize {
+    match end_line {
+      0 => 0,
+      _ => self.block_of_line(end_line - 1) + 1,
+    }
+  }
+
+  /// Compresses `current` up to its last line break.
+  fn seal(&mut self) {
+    let end = match self.current.iter().rposition(|b| *b == b'\n') {
+      Some(pos) => pos + 1,
+      None => self.current.len(),
+    };
+    let rest = self.current.split_off(end);
+    let data = std::mem::replace(&mut self.current, rest);
+    self.blocks.push(Block {
+      data: lz4_flex::compress_prepend_size(&data),
+      first_line: self.current_first_line,
+    });
+    self.current_first_line += count_lines(&data);
+  }
+
+  /// Index of the block with the line break that ends `line`. `current` is
+  /// the block after the compressed ones.
+  fn block_of_line(&self, line: usize) -> usize {
+    let starts = self.blocks.iter().map(|block| block.first_line);
+    let count = starts
+      .chain(std::iter::once(self.current_first_line))
+      .take_while(|first_line| *first_line <= line)
+      .count();
+    count.saturating_sub(1)
+  }
+
+  /// Number of line breaks before the block after `index`.
+  fn block_end_line(&self, index: usize) -> usize {
+    match self.blocks.get(index + 1) {
+      Some(block) => block.first_line,
+      None if index < self.blocks.len() => self.current_first_line,
+      None => self.lines,
+    }
+  }
+
+  /// Uncompressed output of a block and the number of line breaks before it.
+  fn block(&self, index: usize) -> (Vec<u8>, usize) {
+    match self.blocks.get(index) {
+      Some(block) => {
+        let data =
+          lz4_flex::decompress_size_prepended(&block.data).unwrap_or_default();
+        (data, block.first_line)
+      }
+      None => (self.current.clone(), self.current_first_line),
+    }
+  }
+}
+
+impl OutputSink for OutputArchive {
+  fn write(&mut self, bytes: &[u8]) {
+    self.current.extend_from_slice(bytes);
+    self.lines += count_lines(bytes);
+    if self.current.len() >= BLOCK_SIZE {
+      self.seal();
+    }
+  }
+}
+
+fn count_lines(bytes: &[u8]) -> usize {
+  bytes.iter().filter(|b| **b == b'\n').count()
+}
+
+/// Number of lines in the terminal and its scrollback that were ended by a
+/// line break, i.e. lines that are already counted by the archive.
+pub fn terminal_lines(vt: &mut vt100::Parser) -> usize {
+  let len = scrollback_rows(vt);
+  let screen = vt.screen();
+  let scrollback = screen.scrollback();
+  let (rows, _) = screen.size();
+  let total = len + screen.cursor_position().0 as usize;
+
+  let mut lines = 0;
+  let mut row = 0;
+  // Rows of the scrollback are only reachable by scrolling to them.
+  while row < total {
+    let offset = len.saturating_sub(row);
+    vt.set_scrollback(offset);
+    let first = len - offset;
+    for visible in (row - first)..rows as usize {
+      if first + visible >= total {
+        break;
+      }
+      // A wrapped row continues on the next one.
+      if !vt.screen().row_wrapped(visible as u16) {
+        lines += 1;
+      }
+    }
+    row = first + rows as usize;
+  }
+
+  vt.set_scrollback(scrollback);
+  lines
+}
+
+/// Text of the lines before `end_line`, without escape sequences. With
+/// `limit`, only the last lines, and only the blocks that contain them are
+/// read.
+pub fn archived_lines(
+  archive: &OutputArchive,
+  end_line: usize,
+  limit: Option<usize>,
+) -> Vec<String> {
+  if end_line == 0 {
+    return Vec::new();
+  }
+  let mut blocks = Vec::new();
+  let mut count = 0;
+  for block in (0..archive.blocks_before(end_line)).rev() {
+    if matches!(limit, Some(limit) if count >= limit) {
+      break;
+    }
+    let lines = block_lines(archive, block, end_line);
+    count += lines.len();
+    blocks.push(lines);
+  }
+
+  let mut lines = blocks.into_iter().rev().flatten().collect::<Vec<_>>();
+  if let Some(limit) = limit {
+    lines.drain(..lines.len().saturating_sub(limit));
+  }
+  lines
+}
+
+/// Text of the lines of a block that are before `end_line`, without escape
+/// sequences.
+pub fn block_lines(
+  archive: &OutputArchive,
+  block: usize,
+  end_line: usize,
+) -> Vec<String> {
+  let (data, first_line) = archive.block(block);
+  let block_end = archive.block_end_line(block).min(end_line);
+  let mut text = Vec::new();
+  AnsiStripper::new().strip_into(&data, &mut text);
+  text
+    .split(|b| *b == b'\n')
+    .take(block_end.saturating_sub(first_line))
+    .map(|line| String::from_utf8_lossy(line).into_owned())
+    .collect()
+}
+
+/// Output that scrolled out of the terminal of a process.
+pub struct HistoryView {
+  pub vt: VtWrap,
+  block: usize,
+  /// Lines from this one on are in the terminal.
+  end_line: usize,
+}
+
+impl HistoryView {
+  /// Shows the lines before `end_line`, with the last one at the bottom.
+  pub fn open(
+    archive: &OutputArchive,
+    end_line: usize,
+    size: (u16, u16),
+  ) -> Option<Self> {
+    if end_line == 0 {
+      return None;
+    }
+    let block = archive.block_of_line(end_line - 1);
+    Some(Self::open_block(archive, block, end_line, size))
+  }
+
+  /// Shows the lines of `block` before `end_line`, scrolled to the bottom.
+  pub fn open_block(
+    archive: &OutputArchive,
+    block: usize,
+    end_line: usize,
+    size: (u16, u16),
+  ) -> Self {
+    let vt = replay(archive, block, end_line, size);
+    HistoryView {
+      vt: Arc::new(RwLock::new(vt)),
+      block,
+      end_line,
+    }
+  }
+
+  /// Index of the shown block.
+  pub fn block(&self) -> usize {
+    self.block
+  }
+
+  pub fn end_line(&self) -> usize {
+    self.end_line
+  }
+
+  pub fn scroll_up(&mut self, archive: &OutputArchive, n: usize) {
+    let mut vt = match self.vt.write() {
+      Ok(vt) => vt,
+      Err(_) => return,
+    };
+    let size = vt.screen().size();
+    let scrollback = vt.screen().scrollback();
+    if scrollback < scrollback_rows(&mut vt) {
+      vt.set_scrollback(scrollback.saturating_add(n));
+    } else if self.block > 0 {
+      self.block -= 1;
+      *vt = replay(archive, self.block, self.end_line, size);
+    }
+  }
+
+  /// Returns `false` when scrolled past the last line, so the terminal
+  /// should be shown again.
+  pub fn scroll_down(&mut self, archive: &OutputArchive, n: usize) -> bool {
+    let mut vt = match self.vt.write() {
+      Ok(vt) => vt,
+      Err(_) => return false,
+    };
+    let screen = vt.screen();
+    let size = screen.size();
+    if screen.scrollback() > 0 {
+      let pos = usize::saturating_sub(screen.scrollback(), n);
+      vt.set_scrollback(pos);
+    } else if self.block < archive.block_of_line(self.end_line - 1) {
+      self.block += 1;
+      *vt = replay(archive, self.block, self.end_line, size);
+      vt.set_scrollback(usize::MAX);
+    } else {
+      return false;
+    }
+    true
+  }
+
+  pub fn set_size(&self, rows: u16, cols: u16) {
+    if let Ok(mut vt) = self.vt.write() {
+      vt.set_size(rows, cols);
+    }
+  }
+}
+
+/// Feeds a block into a new terminal, leaving out the line break after the
+/// last line so that it is at the bottom of the screen.
+fn replay(
+  archive: &OutputArchive,
+  block: usize,
+  end_line: usize,
+  (rows, cols): (u16, u16),
+) -> vt100::Parser {
+  let (data, first_line) = archive.block(block);
+  let end = data
+    .iter()
+    .enumerate()
+    .filter(|(_, b)| **b == b'\n')
+    .nth(end_line.saturating_sub(first_line + 1))
+    .map_or(data.len(), |(pos, _)| pos + 1);
+  let mut data = &data[..end];
+  data = data.strip_suffix(b"\n").unwrap_or(data);
+  data = data.strip_suffix(b"\r").unwrap_or(data);
+
+  let mut vt = vt100::Parser::new(rows, cols, BLOCK_SIZE);
+  vt.process(data);
+  vt
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn archive(lines: usize) -> OutputArchive {
+    let mut archive = OutputArchive::default();
+    for i in 0..lines {
+      archive.write(format!("line {:05}\r\n", i).as_bytes());
+    }
+    archive
+  }
+
+  #[test]
+  fn blocks() {
+    // 12 bytes per line.
+    let archive = archive(12_000);
+    assert_eq!(archive.lines(), 12_000);
+    assert_eq!(archive.blocks.len(), 2);
+    assert_eq!(archive.blocks[1].first_line, 5462);
+    assert_eq!(archive.current_first_line, 10924);
+
+    assert_eq!(archive.block_of_line(0), 0);
+    assert_eq!(archive.block_of_line(5461), 0);
+    assert_eq!(archive.block_of_line(5462), 1);
+    assert_eq!(archive.block_of_line(11_999), 2);
+    assert_eq!(archive.blocks_before(0), 0);
+    assert_eq!(archive.blocks_before(5462), 1);
+    assert_eq!(archive.blocks_before(5463), 2);
+
+    let (data, first_line) = archive.block(1);
+    assert_eq!(first_line, 5462);
+    assert!(data.starts_with(b"line 05462\r\n"));
+    assert!(data.ends_with(b"line 10923\r\n"));
+  }
+
+  #[test]
+  fn scroll_history() {
+    let archive = archive(12_000);
+    // `Screen::contents` includes rows above the screen when it is scrolled
+    // further than its height.
+    let contents = |view: &HistoryView| {
+      let vt = view.vt.read().unwrap();
+      vt.screen()
+        .rows(0, 20)
+        .take(3)
+        .collect::<Vec<_>>()
+        .join("\n")
+    };
+
+    let mut view = HistoryView::open(&archive, 6000, (3, 20)).unwrap();
+    assert_eq!(contents(&view), "line 05997\nline 05998\nline 05999");
+
+    view.scroll_up(&archive, 537);
+    assert_eq!(contents(&view), "line 05462\nline 05463\nline 05464");
+    view.scroll_up(&archive, 5);
+    assert_eq!(contents(&view), "line 05459\nline 05460\nline 05461");
+
+    assert!(view.scroll_down(&archive, 5));
+    assert_eq!(contents(&view), "line 05462\nline 05463\nline 05464");
+    view.scroll_down(&archive, 1000);
+    assert!(!view.scroll_down(&archive, 1));
+  }
+
+  #[test]
+  fn read_archived_lines() {
+    let archive = archive(12_000);
+    let lines = archived_lines(&archive, 11_000, None);
+    assert_eq!(lines.len(), 11_000);
+    assert_eq!(lines[0], "line 00000");
+    assert_eq!(lines[5462], "line 05462");
+    assert_eq!(lines[10_999], "line 10999");
+
+    let lines = block_lines(&archive, 1, 6000);
+    assert_eq!(lines.len(), 538);
+    assert_eq!(lines[0], "line 05462");
+
+    let lines = archived_lines(&archive, 6000, Some(2));
+    assert_eq!(lines, vec!["line 05998", "line 05999"]);
+    assert!(archived_lines(&archive, 0, None).is_empty());
+
+    let mut archive = OutputArchive::default();
+    archive.write(b"\x1b[1mone\x1b[0m\r\n\r\n\r\ntwo");
+    let lines = archived_lines(&archive, 3, None);
+    assert_eq!(lines, vec!["one", "", ""]);
+  }
+
+  #[test]
+  fn count_terminal_lines() {
+    let mut vt = vt100::Parser::new(3, 10, 100);
+    vt.process(b"one\r\ntwo is a long line\r\nthree\r\nfour");
+    vt.set_scrollback(1);
+    assert_eq!(terminal_lines(&mut vt), 3);
+    assert_eq!(vt.screen().scrollback(), 1);
+  }
+}
@@ -0,0 +1,161 @@
+use indexmap::IndexMap;
+
+/// Variables available to `${NAME}` references in process configs.
+pub struct Vars {
+  /// Directory of the config file.
+  pub config_dir: Option<String>,
+  pub port_offset: usize,
+}
+
+impl Vars {
+  /// Looks up a variable. Variables provided by mprocs come first, then the
+  /// process `env` and then the environment of mprocs.
+  pub fn get(
+    &self,
+    name: &str,
+    proc_name: &str,
+    env: Option<&IndexMap<String, Option<String>>>,
+  ) -> Option<String> {
+    match name {
+      "CONFIG_DIR" | "PROC_NAME" | "PORT_OFFSET" => {
+        self.get_provided(name, proc_name)
+      }
+      _ => match env.and_then(|env| env.get(name)) {
+        Some(value) => value.clone(),
+        None => std::env::var(name).ok(),
+      },
+    }
+  }
+
+  /// Looks up a variable provided by mprocs. These are the only ones that
+  /// are replaced in shell commands, the shell expands the others.
+  pub fn get_provided(&self, name: &str, proc_name: &str) -> Option<String> {
+    match name {
+      "CONFIG_DIR" => self.config_dir.clone(),
+      "PROC_NAME" => Some(proc_name.to_string()),
+      "PORT_OFFSET" => Some(self.port_offset.to_string()),
+      _ => None,
+    }
+  }
+}
+
+/// Replaces `${NAME}` and `${NAME:-default}` references. The default is used
+/// when the variable is undefined or empty. `$${` is replaced with `${`.
+pub fn interpolate(
+  s: &str,
+  lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+  replace_vars(s, lookup, false)
+}
+
+/// Like `interpolate`, but keeps references that are undefined, unclosed or
+/// not a variable name as they are, so that the shell can expand things like
+/// `${f}` or `${PWD##*/}`.
+pub fn interpolate_shell(
+  s: &str,
+  lookup: impl Fn(&str) -> Option<String>,
+) -> String {
+  // Never fails when undefined references are kept.
+  replace_vars(s, lookup, true).unwrap_or_else(|_| s.to_string())
+}
+
+fn replace_vars(
+  s: &str,
+  lookup: impl Fn(&str) -> Option<String>,
+  keep_undefined: bool,
+) -> Result<String, String> {
+  let mut out = String::with_capacity(s.len());
+  let mut rest = s;
+
+  while let Some(pos) = rest.find('$') {
+    out.push_str(&rest[..pos]);
+    rest = &rest[pos..];
+
+    if let Some(after) = rest.strip_prefix("$${") {
+      out.push_str("${");
+      rest = after;
+    } else if let Some(after) = rest.strip_prefix("${") {
+      let end = match after.find('}') {
+        Some(end) => end,
+        None if keep_undefined => break,
+        None => return Err("Unclosed \"${\"".to_string()),
+      };
+      let reference = &after[..end];
+      let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+      };
+      if name.is_empty()
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+      {
+        if keep_undefined {
+          out.push_str(&rest[..end + 3]);
+          rest = &after[end + 1..];
+          continue;
+        }
+        return Err(format!("Invalid variable name \"{}\"", name));
+      }
+      match (lookup(name), default) {
+        (Some(value), Some(default)) if value.is_empty() => {
+          out.push_str(default)
+        }
+        (Some(value), _) => out.push_str(&value),
+        (None, _) if keep_undefined => out.push_str(&rest[..end + 3]),
+        (None, Some(default)) => out.push_str(default),
+        (None, None) => {
+          return Err(format!("Undefined variable \"{}\"", name));
+        }
+      }
+      rest = &after[end + 1..];
+    } else {
+      out.push('$');
+      rest = &rest[1..];
+    }
+  }
+  out.push_str(rest);
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lookup(name: &str) -> Option<String> {
+    match name {
+      "A" => Some("a".to_string()),
+      "EMPTY" => Some(String::new()),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn interpolation() {
+    assert_eq!(
+      interpolate("x ${A} $B $$ y", lookup).unwrap(),
+      "x a $B $$ y"
+    );
+    assert_eq!(
+      interpolate("${B:-b}${EMPTY:-e}${A:-z}", lookup).unwrap(),
+      "bea"
+    );
+    assert_eq!(interpolate("$${A} ${A}", lookup).unwrap(), "${A} a");
+    assert_eq!(
+      interpolate("${B}", lookup).unwrap_err(),
+      "Undefined variable \"B\""
+    );
+    assert_eq!(interpolate("${A", lookup).unwrap_err(), "Unclosed \"${\"");
+  }
+
+  #[test]
+  fn shell_interpolation() {
+    assert_eq!(
+      interpolate_shell("for f in *; do echo ${f} ${A}; done", lookup),
+      "for f in *; do echo ${f} a; done"
+    );
+    assert_eq!(
+      interpolate_shell("${PWD##*/} ${#A} ${B:-b} $${A} ${A", lookup),
+      "${PWD##*/} ${#A} ${B:-b} ${A} ${A"
+    );
+  }
+}
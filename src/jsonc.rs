@@ -0,0 +1,86 @@
+/// Turns JSON with comments into plain JSON: removes `//` and `/* */`
+/// comments and trailing commas. Comments are replaced with spaces, so
+/// parse errors point to the same line and column as in the source.
+pub fn strip_jsonc(src: &str) -> String {
+  let mut out = String::with_capacity(src.len());
+  // Index in `out` of a comma that may turn out to be trailing.
+  let mut pending_comma: Option<usize> = None;
+  let mut chars = src.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => {
+        pending_comma = None;
+        out.push(c);
+        while let Some(c) = chars.next() {
+          out.push(c);
+          match c {
+            '\\' => {
+              if let Some(c) = chars.next() {
+                out.push(c);
+              }
+            }
+            '"' => break,
+            _ => (),
+          }
+        }
+      }
+      '/' if chars.peek() == Some(&'/') => {
+        out.push(' ');
+        for c in chars.by_ref() {
+          if c == '\n' {
+            out.push(c);
+            break;
+          }
+          out.push(' ');
+        }
+      }
+      '/' if chars.peek() == Some(&'*') => {
+        chars.next();
+        out.push_str("  ");
+        let mut prev = ' ';
+        for c in chars.by_ref() {
+          out.push(if c == '\n' { '\n' } else { ' ' });
+          if prev == '*' && c == '/' {
+            break;
+          }
+          prev = c;
+        }
+      }
+      ',' => {
+        pending_comma = Some(out.len());
+        out.push(c);
+      }
+      '}' | ']' => {
+        if let Some(i) = pending_comma.take() {
+          out.replace_range(i..i + 1, " ");
+        }
+        out.push(c);
+      }
+      c if c.is_whitespace() => out.push(c),
+      c => {
+        pending_comma = None;
+        out.push(c);
+      }
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strips_comments_and_trailing_commas() {
+    assert_eq!(
+      strip_jsonc("{\"a\": 1, // one\n\"b\": [2, /* two */ 3,],}"),
+      "{\"a\": 1,       \n\"b\": [2,           3 ] }"
+    );
+    assert_eq!(
+      strip_jsonc(r#"{"url": "http://x/*y*/", "q": "\"//\""}"#),
+      r#"{"url": "http://x/*y*/", "q": "\"//\""}"#
+    );
+  }
+}
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use anyhow::bail;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use serde::{Deserialize, Serialize};
@@ -81,8 +83,8 @@ impl From<KeyCode> for Key {
   }
 }
 
-impl ToString for Key {
-  fn to_string(&self) -> String {
+impl Display for Key {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let mut buf = String::new();
 
     buf.push('<');
@@ -130,7 +132,7 @@ impl ToString for Key {
 
     buf.push('>');
 
-    buf
+    f.write_str(&buf)
   }
 }
 
@@ -0,0 +1,110 @@
+//! Writes process output to log files, so output that scrolled out of the
+//! terminal can still be searched.
+
+use std::{
+  ffi::OsString,
+  fs::{self, File, OpenOptions},
+  io::{self, Write},
+  path::PathBuf,
+};
+
+use crate::{config::LogConfig, proc::OutputSink, strip_ansi::AnsiStripper};
+
+pub struct LogWriter {
+  path: PathBuf,
+  rotate: Option<u64>,
+  keep: usize,
+  stripper: Option<AnsiStripper>,
+  /// Opened on the first write.
+  file: Option<File>,
+  size: u64,
+  buf: Vec<u8>,
+  /// Set after an error, so it is reported only once.
+  failed: bool,
+}
+
+impl LogWriter {
+  pub fn new(cfg: &LogConfig) -> Self {
+    LogWriter {
+      path: cfg.file.clone(),
+      rotate: cfg.rotate,
+      keep: cfg.keep,
+      stripper: cfg.strip_ansi.then(AnsiStripper::new),
+      file: None,
+      size: 0,
+      buf: Vec::new(),
+      failed: false,
+    }
+  }
+
+  fn open(&mut self) -> io::Result<&mut File> {
+    if self.file.is_none() {
+      if let Some(dir) = self.path.parent() {
+        fs::create_dir_all(dir)?;
+      }
+      let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&self.path)?;
+      self.size = file.metadata()?.len();
+      self.file = Some(file);
+    }
+    Ok(self.file.as_mut().unwrap())
+  }
+
+  /// Renames `app.log` to `app.log.1`, `app.log.1` to `app.log.2` and so on.
+  /// The oldest file is overwritten.
+  fn rotate(&mut self) -> io::Result<()> {
+    self.file = None;
+    if self.keep == 0 {
+      return fs::remove_file(&self.path);
+    }
+    for i in (1..self.keep).rev() {
+      let from = self.numbered(i);
+      if from.exists() {
+        fs::rename(from, self.numbered(i + 1))?;
+      }
+    }
+    fs::rename(&self.path, self.numbered(1))
+  }
+
+  fn numbered(&self, i: usize) -> PathBuf {
+    let mut path = OsString::from(&self.path);
+    path.push(format!(".{}", i));
+    PathBuf::from(path)
+  }
+
+  fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+    self.open()?;
+    if let Some(limit) = self.rotate {
+      if self.size > 0 && self.size + bytes.len() as u64 > limit {
+        self.rotate()?;
+      }
+    }
+    self.open()?.write_all(bytes)?;
+    self.size += bytes.len() as u64;
+    Ok(())
+  }
+}
+
+impl OutputSink for LogWriter {
+  fn write(&mut self, bytes: &[u8]) {
+    if self.failed {
+      return;
+    }
+    let mut buf = std::mem::take(&mut self.buf);
+    let bytes = match &mut self.stripper {
+      Some(stripper) => {
+        buf.clear();
+        stripper.strip_into(bytes, &mut buf);
+        &buf
+      }
+      None => bytes,
+    };
+    if let Err(err) = self.write_bytes(bytes) {
+      log::error!("Failed to write log file {:?}: {}", self.path, err);
+      self.failed = true;
+    }
+    self.buf = buf;
+  }
+}
@@ -4,40 +4,62 @@ mod clipboard;
 mod config;
 mod config_lua;
 mod ctl;
+mod dotenv;
 mod encode_term;
 mod error;
 mod event;
+mod headless;
+mod history;
+mod interpolate;
+mod jsonc;
 mod key;
 mod keymap;
+mod log_file;
 mod package_json;
+mod probe;
 mod proc;
+mod schema;
+mod search;
+mod selector;
 mod settings;
+#[cfg(not(windows))]
+mod signal;
 mod state;
+mod strip_ansi;
 mod theme;
 mod ui_add_proc;
 mod ui_confirm_quit;
 mod ui_keymap;
 mod ui_procs;
 mod ui_remove_proc;
+mod ui_search;
 mod ui_term;
 mod ui_zoom_tip;
+mod watch;
 mod yaml_val;
 
 use std::{
   io::Read,
   path::{Path, PathBuf},
+  process::ExitCode,
 };
 
 use anyhow::{bail, Result};
 use clap::Parser;
 use cli::Options;
-use config::{CmdConfig, Config, ConfigContext, ProcConfig, ServerConfig};
+use config::{
+  merge_config, rebase_included_paths, CmdConfig, Config, ConfigContext,
+  ProcConfig, RestartConfig, Scrollback, ServerConfig, DEFAULT_STOP_TIMEOUT,
+};
 use config_lua::load_lua_config;
 use ctl::run_ctl;
 use flexi_logger::FileSpec;
+use headless::run_headless;
+use jsonc::strip_jsonc;
 use keymap::Keymap;
 use package_json::load_npm_procs;
 use proc::StopSignal;
+use schema::config_schema;
 use serde_yaml::Value;
 use settings::Settings;
 use yaml_val::Val;
@@ -45,7 +67,7 @@ use yaml_val::Val;
 use crate::app::App;
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
+async fn main() -> ExitCode {
   let logger_str = if cfg!(debug_assertions) {
     "info"
   } else {
@@ -60,49 +82,40 @@ async fn main() -> Result<(), std::io::Error> {
     .unwrap();
 
   match run_app().await {
-    Ok(()) => Ok(()),
+    Ok(code) => ExitCode::from(code.clamp(0, 255) as u8),
     Err(err) => {
       eprintln!("Error: {}", err);
-      Ok(())
+      ExitCode::FAILURE
     }
   }
 }
 
-async fn run_app() -> anyhow::Result<()> {
+/// Returns the exit code of mprocs.
+async fn run_app() -> anyhow::Result<i32> {
   let options = Options::parse();
 
-  let config_value = load_config_value(options.config)
-    .map_err(|e| anyhow::Error::msg(format!("[{}] {}", "config", e)))?;
-
-  let mut settings = Settings::default();
+  if options.print_schema {
+    println!("{}", serde_json::to_string_pretty(&config_schema())?);
+    return Ok(0);
+  }
 
-  // merge ~/.config/mprocs/mprocs.yaml
-  settings.merge_from_xdg().map_err(|e| {
-    anyhow::Error::msg(format!("[{}] {}", "global settings", e))
-  })?;
-  // merge ./mprocs.yaml
-  if let Some((value, _)) = &config_value {
-    settings
-      .merge_value(Val::new(value)?)
-      .map_err(|e| anyhow::Error::msg(format!("[{}] {}", "local config", e)))?;
+  if options.check {
+    check_config(options.config, options.profile)?;
+    return Ok(0);
   }
 
-  let mut keymap = Keymap::new();
-  settings.add_to_keymap(&mut keymap)?;
+  let (mut config, keymap, config_ctx) = load_config(options.config)?;
+  let config_path = config_ctx.map(|ctx| ctx.path);
+  let procs_from_args = !options.commands.is_empty() || options.npm;
 
   let config = {
-    let mut config = if let Some((v, ctx)) = config_value {
-      Config::from_value(&v, &ctx, &settings)?
-    } else {
-      Config::make_default(&settings)
-    };
-
     if let Some(server_addr) = options.server {
-      config.server = Some(ServerConfig::Tcp(server_addr));
+      config.server = Some(ServerConfig::from_str(&server_addr)?);
     }
 
     if let Some(control) = options.control {
-      return run_ctl(control.as_str(), &config).await;
+      run_ctl(control.as_str(), &config).await?;
+      return Ok(0);
     }
 
     if !options.commands.is_empty() {
@@ -122,6 +135,14 @@ async fn run_app() -> anyhow::Result<()> {
           cwd: None,
           autostart: true,
           stop: StopSignal::default(),
+          stop_timeout: DEFAULT_STOP_TIMEOUT,
+          restart: RestartConfig::default(),
+          depends_on: Vec::new(),
+          tags: Vec::new(),
+          ready_when: None,
+          watch: None,
+          log: None,
+          scrollback: Scrollback::default(),
         })
         .collect::<Vec<_>>();
 
@@ -129,58 +150,193 @@ async fn run_app() -> anyhow::Result<()> {
     } else if options.npm {
       let procs = load_npm_procs()?;
       config.procs = procs;
+    } else if let Some(profile) = &options.profile {
+      config.apply_profile(profile)?;
+    }
+
+    if procs_from_args {
+      for proc in &mut config.procs {
+        proc.log = config
+          .log
+          .as_ref()
+          .map(|log| log.resolve(&proc.name, Path::new("")));
+        proc.scrollback = config.scrollback;
+      }
     }
 
     config
   };
 
-  let app = App::from_config_file(config, keymap)?;
+  if options.headless {
+    return run_headless(config).await;
+  }
+
+  let app = App::from_config_file(
+    config,
+    keymap,
+    config_path,
+    procs_from_args,
+    options.profile,
+  )?;
   app.run().await
 }
 
+/// Loads the config like a normal start would and reports problems.
+fn check_config(path: Option<PathBuf>, profile: Option<String>) -> Result<()> {
+  let (mut config, _keymap, ctx) = load_config(path)?;
+  let ctx = match ctx {
+    Some(ctx) => ctx,
+    None => bail!("No config file found."),
+  };
+  if let Some(profile) = profile {
+    config.apply_profile(&profile)?;
+  }
+
+  for warning in &config.warnings {
+    eprintln!("Warning: {}", warning);
+  }
+  println!("Config '{}' is valid.", ctx.path.display());
+  Ok(())
+}
+
+/// Loads the config file together with global and local settings. Also used
+/// to reload the config while running.
+pub fn load_config(
+  path: Option<PathBuf>,
+) -> Result<(Config, Keymap, Option<ConfigContext>)> {
+  let config_value = load_config_value(path)
+    .map_err(|e| anyhow::Error::msg(format!("[{}] {}", "config", e)))?;
+
+  let mut settings = Settings::default();
+
+  // merge ~/.config/mprocs/mprocs.yaml
+  settings.merge_from_xdg().map_err(|e| {
+    anyhow::Error::msg(format!("[{}] {}", "global settings", e))
+  })?;
+  // merge ./mprocs.yaml
+  if let Some((value, _, _)) = &config_value {
+    settings
+      .merge_value(Val::new(value)?)
+      .map_err(|e| anyhow::Error::msg(format!("[{}] {}", "local config", e)))?;
+  }
+
+  let mut keymap = Keymap::new();
+  settings.add_to_keymap(&mut keymap)?;
+
+  match config_value {
+    Some((v, ctx, files)) => {
+      let mut config = Config::from_value(&v, &ctx, &settings)?;
+      config.files.splice(0..0, files);
+      for warning in &config.warnings {
+        log::warn!("{}", warning);
+      }
+      Ok((config, keymap, Some(ctx)))
+    }
+    None => Ok((Config::make_default(&settings), keymap, None)),
+  }
+}
+
 fn load_config_value(
   config: Option<PathBuf>,
-) -> Result<Option<(Value, ConfigContext)>> {
+) -> Result<Option<(Value, ConfigContext, Vec<PathBuf>)>> {
   if let Some(path) = config {
-    return Ok(Some((
-      read_value(path.to_str().unwrap())?,
-      ConfigContext { path },
-    )));
+    return read_config_file(path).map(Some);
   }
 
   {
     let path = "mprocs.lua";
     if Path::new(path).is_file() {
-      return Ok(Some((
-        read_value(path)?,
-        ConfigContext { path: path.into() },
-      )));
+      return read_config_file(path.into()).map(Some);
     }
   }
 
   {
     let path = "mprocs.yaml";
     if Path::new(path).is_file() {
-      return Ok(Some((
-        read_value(path)?,
-        ConfigContext { path: path.into() },
-      )));
+      return read_config_file(path.into()).map(Some);
     }
   }
 
   {
     let path = "mprocs.json";
     if Path::new(path).is_file() {
-      return Ok(Some((
-        read_value(path)?,
-        ConfigContext { path: path.into() },
-      )));
+      return read_config_file(path.into()).map(Some);
+    }
+  }
+
+  {
+    let path = "mprocs.jsonc";
+    if Path::new(path).is_file() {
+      return read_config_file(path.into()).map(Some);
     }
   }
 
   Ok(None)
 }
 
+/// Reads a config file with its includes. Also returns the paths of all files
+/// that were read.
+fn read_config_file(
+  path: PathBuf,
+) -> Result<(Value, ConfigContext, Vec<PathBuf>)> {
+  let mut files = Vec::new();
+  let value = read_config(&path, &mut Vec::new(), &mut files)?;
+  Ok((value, ConfigContext { path }, files))
+}
+
+/// Reads a config file and merges the files listed in its `include` field
+/// under it. Included paths are relative to the including file.
+fn read_config(
+  path: &Path,
+  stack: &mut Vec<PathBuf>,
+  files: &mut Vec<PathBuf>,
+) -> Result<Value> {
+  let canonical =
+    dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+  if stack.contains(&canonical) {
+    bail!("Config file '{}' includes itself.", path.display());
+  }
+
+  files.push(path.to_path_buf());
+  let mut value = read_value(path.to_str().unwrap())?;
+  if !value.is_mapping() {
+    return Ok(value);
+  }
+  // Paths in included files are relative to their own directory.
+  if !stack.is_empty() {
+    if let Some(dir) = canonical.parent() {
+      rebase_included_paths(&mut value, dir);
+    }
+  }
+  let includes =
+    match Val::new(&value)?.as_object()?.get(&Value::from("include")) {
+      Some(include) => include
+        .as_array()?
+        .into_iter()
+        .map(|v| v.as_str().map(|s| s.to_owned()))
+        .collect::<Result<Vec<_>>>()?,
+      None => return Ok(value),
+    };
+
+  stack.push(canonical);
+  let dir = path.parent().unwrap_or_else(|| Path::new(""));
+  let mut merged = Value::Mapping(Default::default());
+  for include in includes {
+    let include = dir.join(include);
+    let included = read_config(&include, stack, files).map_err(|e| {
+      anyhow::format_err!("{} (included from '{}')", e, path.display())
+    })?;
+    merge_config(&mut merged, included);
+  }
+  stack.pop();
+
+  merge_config(&mut merged, value);
+  if let Value::Mapping(map) = &mut merged {
+    map.remove(&Value::from("include"));
+  }
+  Ok(merged)
+}
+
 fn read_value(path: &str) -> Result<Value> {
   // Open the file in read-only mode with buffer.
   let file = match std::fs::File::open(path) {
@@ -203,7 +359,12 @@ fn read_value(path: &str) -> Result<Value> {
       reader.read_to_string(&mut buf)?;
       load_lua_config(path, &buf)?
     }
-    _ => bail!("Supported config extensions: lua, yaml, yml."),
+    "json" | "jsonc" => {
+      let mut buf = String::new();
+      reader.read_to_string(&mut buf)?;
+      serde_json::from_str(&strip_jsonc(&buf))?
+    }
+    _ => bail!("Supported config extensions: lua, yaml, yml, json, jsonc."),
   };
   Ok(value)
 }
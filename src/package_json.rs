@@ -5,7 +5,9 @@ use indexmap::IndexMap;
 use serde::Deserialize;
 
 use crate::{
-  config::{CmdConfig, ProcConfig},
+  config::{
+    CmdConfig, ProcConfig, RestartConfig, Scrollback, DEFAULT_STOP_TIMEOUT,
+  },
   proc::StopSignal,
 };
 
@@ -46,6 +48,15 @@ pub fn load_npm_procs() -> Result<Vec<ProcConfig>> {
     autostart: false,
 
     stop: StopSignal::default(),
+
+    stop_timeout: DEFAULT_STOP_TIMEOUT,
+    restart: RestartConfig::default(),
+    depends_on: Vec::new(),
+    tags: Vec::new(),
+    ready_when: None,
+    watch: None,
+    log: None,
+    scrollback: Scrollback::default(),
   });
   Ok(procs.collect())
 }
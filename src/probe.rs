@@ -0,0 +1,198 @@
+use std::{
+  ffi::OsString,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+
+use indexmap::IndexMap;
+use tokio::{net::TcpStream, sync::mpsc::UnboundedSender, sync::Notify};
+
+use crate::{
+  config::{ProcConfig, ReadyConfig},
+  proc::ProcUpdate,
+  strip_ansi::AnsiStripper,
+};
+
+/// Checks `ready_when` conditions of a process and sends `ProcUpdate::Ready`
+/// once all of them are met.
+pub struct ReadyProbe {
+  cfg: ReadyConfig,
+  cwd: Option<OsString>,
+  env: Option<IndexMap<String, Option<String>>>,
+}
+
+/// Shared between the pty reader and the probe task of a single instance.
+#[derive(Default)]
+pub struct LogSignal {
+  matched: AtomicBool,
+  notify: Notify,
+}
+
+/// Fed by the pty reader with the raw output of the process.
+pub struct LogWatch {
+  matcher: LogMatcher,
+  signal: Arc<LogSignal>,
+}
+
+impl LogWatch {
+  pub fn feed(&mut self, bytes: &[u8]) {
+    if !self.signal.matched.load(Ordering::Relaxed) && self.matcher.feed(bytes)
+    {
+      self.signal.matched.store(true, Ordering::Relaxed);
+      self.signal.notify.notify_one();
+    }
+  }
+}
+
+impl ReadyProbe {
+  pub fn new(cfg: &ProcConfig) -> Option<Self> {
+    cfg.ready_when.as_ref().map(|ready_when| ReadyProbe {
+      cfg: ready_when.clone(),
+      cwd: cfg.cwd.clone(),
+      env: cfg.env.clone(),
+    })
+  }
+
+  pub fn log_watch(&self, signal: &Arc<LogSignal>) -> Option<LogWatch> {
+    self.cfg.log.as_ref().map(|pattern| LogWatch {
+      matcher: LogMatcher::new(pattern),
+      signal: signal.clone(),
+    })
+  }
+
+  /// Starts polling the conditions for a freshly spawned instance. The task
+  /// ends when the conditions are met or the instance stops running.
+  pub fn spawn(
+    &self,
+    id: usize,
+    running: Arc<AtomicBool>,
+    log: Arc<LogSignal>,
+    tx: UnboundedSender<(usize, ProcUpdate)>,
+  ) {
+    let cfg = self.cfg.clone();
+    let cwd = self.cwd.clone();
+    let env = self.env.clone();
+    tokio::spawn(async move {
+      let mut port_ok = cfg.port.is_none();
+      let mut cmd_ok = cfg.cmd.is_none();
+      loop {
+        if !running.load(Ordering::Relaxed) {
+          return;
+        }
+
+        let log_ok = cfg.log.is_none() || log.matched.load(Ordering::Relaxed);
+        if let (false, Some(addr)) = (port_ok, &cfg.port) {
+          port_ok = matches!(
+            tokio::time::timeout(cfg.interval, TcpStream::connect(addr)).await,
+            Ok(Ok(_))
+          );
+        }
+        // Only run the command when everything else is ready.
+        if let (false, true, true, Some(cmd)) =
+          (cmd_ok, log_ok, port_ok, &cfg.cmd)
+        {
+          cmd_ok = run_check_cmd(cmd, &cwd, &env).await;
+        }
+
+        if log_ok && port_ok && cmd_ok {
+          if running.load(Ordering::Relaxed) {
+            let _res = tx.send((id, ProcUpdate::Ready));
+          }
+          return;
+        }
+
+        let _res =
+          tokio::time::timeout(cfg.interval, log.notify.notified()).await;
+      }
+    });
+  }
+}
+
+async fn run_check_cmd(
+  cmd: &str,
+  cwd: &Option<OsString>,
+  env: &Option<IndexMap<String, Option<String>>>,
+) -> bool {
+  let mut command = if cfg!(windows) {
+    let mut command = tokio::process::Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+  } else {
+    let mut command = tokio::process::Command::new("/bin/sh");
+    command.arg("-c").arg(cmd);
+    command
+  };
+  command
+    .stdin(std::process::Stdio::null())
+    .stdout(std::process::Stdio::null())
+    .stderr(std::process::Stdio::null())
+    .kill_on_drop(true);
+  if let Some(cwd) = cwd {
+    command.current_dir(cwd);
+  }
+  if let Some(env) = env {
+    for (k, v) in env {
+      match v {
+        Some(v) => command.env(k, v),
+        None => command.env_remove(k),
+      };
+    }
+  }
+
+  match command.status().await {
+    Ok(status) => status.success(),
+    Err(err) => {
+      log::warn!("Failed to run readiness command `{}`: {}", cmd, err);
+      false
+    }
+  }
+}
+
+/// Finds a plain text pattern in a stream of terminal output.
+struct LogMatcher {
+  pattern: Vec<u8>,
+  stripper: AnsiStripper,
+  buf: Vec<u8>,
+}
+
+impl LogMatcher {
+  fn new(pattern: &str) -> Self {
+    LogMatcher {
+      pattern: pattern.as_bytes().to_vec(),
+      stripper: AnsiStripper::new(),
+      buf: Vec::new(),
+    }
+  }
+
+  fn feed(&mut self, bytes: &[u8]) -> bool {
+    self.stripper.strip_into(bytes, &mut self.buf);
+    if self.pattern.is_empty()
+      || self
+        .buf
+        .windows(self.pattern.len())
+        .any(|window| window == self.pattern.as_slice())
+    {
+      return true;
+    }
+    // Keep the tail that may be the beginning of a match.
+    let keep = self.pattern.len() - 1;
+    if self.buf.len() > keep {
+      self.buf.drain(..self.buf.len() - keep);
+    }
+    false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn log_matcher() {
+    let mut matcher = LogMatcher::new("Listening on");
+    assert!(!matcher.feed(b"Starting...\r\n\x1b[32mListen"));
+    assert!(matcher.feed(b"ing\x1b[0m on :3000\r\n"));
+  }
+}
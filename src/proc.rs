@@ -1,32 +1,52 @@
 use std::fmt::Debug;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread::{self, spawn};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use assert_matches::assert_matches;
 use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
 use portable_pty::MasterPty;
-use portable_pty::{native_pty_system, ChildKiller, CommandBuilder, PtySize};
+use portable_pty::{
+  native_pty_system, Child, ChildKiller, CommandBuilder, PtySize,
+};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::task::spawn_blocking;
 use tui::layout::Rect;
 use vt100::MouseProtocolMode;
 
-use crate::config::{Config, ProcConfig};
+use crate::config::{
+  Config, ProcConfig, RestartConfig, RestartPolicy, Scrollback,
+};
 use crate::encode_term::{encode_key, encode_mouse_event, KeyCodeEncodeModes};
 use crate::error::ResultLogger;
+use crate::history::{
+  archived_lines, block_lines, terminal_lines, HistoryView, OutputArchive,
+};
 use crate::key::Key;
+use crate::log_file::LogWriter;
+use crate::probe::{LogSignal, LogWatch, ReadyProbe};
+use crate::search::Search;
+#[cfg(not(windows))]
+use crate::signal;
+use crate::watch::FileWatcher;
 
 pub struct Inst {
-  pub vt: VtWrap,
+  vt: VtWrap,
+  /// All output, with `scrollback: unlimited`.
+  archive: Option<Arc<Mutex<OutputArchive>>>,
+  /// Shown instead of `vt` while scrolled past its scrollback.
+  history: Option<HistoryView>,
 
   pub pid: u32,
   pub master: Box<dyn MasterPty + Send>,
   pub killer: Box<dyn ChildKiller + Send + Sync>,
 
   pub running: Arc<AtomicBool>,
+  /// The exited process is reaped once this is dropped with the instance,
+  /// so `pid` can't be reused while signals may still be sent to it.
+  _keep_pid: mpsc::Sender<()>,
 }
 
 impl Debug for Inst {
@@ -40,14 +60,36 @@ impl Debug for Inst {
 
 pub type VtWrap = Arc<RwLock<vt100::Parser>>;
 
+/// Receives a copy of everything a process writes to its terminal.
+pub trait OutputSink: Send {
+  fn write(&mut self, bytes: &[u8]);
+
+  /// Called when the config of the process is reloaded.
+  fn update_config(&mut self, _cfg: &ProcConfig) {}
+}
+
+/// Sinks are shared by all instances of a process.
+pub type SharedSink = Arc<Mutex<dyn OutputSink>>;
+
 impl Inst {
   fn spawn(
     id: usize,
     cmd: CommandBuilder,
     tx: UnboundedSender<(usize, ProcUpdate)>,
     size: &Size,
+    mut log_watch: Option<LogWatch>,
+    mut output: Vec<SharedSink>,
+    scrollback: Scrollback,
   ) -> anyhow::Result<Self> {
-    let vt = vt100::Parser::new(size.height, size.width, 1000);
+    let (scrollback_len, archive) = match scrollback {
+      Scrollback::Lines(lines) => (lines, None),
+      Scrollback::Unlimited => {
+        let archive = Arc::new(Mutex::new(OutputArchive::default()));
+        output.push(archive.clone());
+        (LIVE_SCROLLBACK, Some(archive))
+      }
+    };
+    let vt = vt100::Parser::new(size.height, size.width, scrollback_len);
     let vt = Arc::new(RwLock::new(vt));
 
     let pty_system = native_pty_system();
@@ -59,38 +101,43 @@ impl Inst {
     })?;
 
     let running = Arc::new(AtomicBool::new(true));
-    let mut child = pair.slave.spawn_command(cmd)?;
+    let child = pair.slave.spawn_command(cmd)?;
     let pid = child.process_id().unwrap_or(0);
     let killer = child.clone_killer();
 
     let mut reader = pair.master.try_clone_reader().unwrap();
+    // Disconnected when the reader is done.
+    let (reader_done_tx, reader_done_rx) = mpsc::channel::<()>();
+    let (keep_pid, keep_pid_rx) = mpsc::channel::<()>();
 
     {
       let tx = tx.clone();
       let vt = vt.clone();
       let running = running.clone();
       spawn_blocking(move || {
+        let _reader_done_tx = reader_done_tx;
         let mut buf = [0; 4 * 1024];
-        loop {
-          if !running.load(Ordering::Relaxed) {
-            break;
-          }
-
-          match reader.read(&mut buf[..]) {
-            Ok(count) => {
-              if count > 0 {
-                if let Ok(mut vt) = vt.write() {
-                  vt.process(&buf[..count]);
-                  match tx.send((id, ProcUpdate::Render)) {
-                    Ok(_) => (),
-                    Err(_) => break,
-                  }
-                }
-              } else {
-                thread::sleep(Duration::from_millis(10));
+        while let Ok(count) = reader.read(&mut buf[..]) {
+          if count > 0 {
+            if let Some(log_watch) = &mut log_watch {
+              log_watch.feed(&buf[..count]);
+            }
+            for sink in &output {
+              if let Ok(mut sink) = sink.lock() {
+                sink.write(&buf[..count]);
               }
             }
-            _ => break,
+            if let Ok(mut vt) = vt.write() {
+              vt.process(&buf[..count]);
+              match tx.send((id, ProcUpdate::Render)) {
+                Ok(_) => (),
+                Err(_) => break,
+              }
+            }
+          } else if !running.load(Ordering::Relaxed) {
+            break;
+          } else {
+            thread::sleep(Duration::from_millis(10));
           }
         }
       });
@@ -102,24 +149,48 @@ impl Inst {
       let running = running.clone();
       spawn(move || {
         // Block until program exits
-        let _status = child.wait();
+        let status = wait_child(child, pid);
         running.store(false, Ordering::Relaxed);
-        let _result = tx.send((id, ProcUpdate::Stopped));
+        // Let the reader drain the remaining output first, unless the
+        // terminal is kept open by orphaned children.
+        let _ = reader_done_rx.recv_timeout(READER_DRAIN_TIMEOUT);
+        let _result = tx.send((id, ProcUpdate::Stopped(status)));
+        // Waits until the instance is dropped.
+        let _ = keep_pid_rx.recv();
+        #[cfg(not(windows))]
+        signal::reap(pid);
       });
     }
 
     let inst = Inst {
       vt,
+      archive,
+      history: None,
 
       pid,
       master: pair.master,
       killer,
 
       running,
+      _keep_pid: keep_pid,
     };
     Ok(inst)
   }
 
+  #[cfg(not(windows))]
+  fn kill(&mut self) {
+    if self.pid == 0 {
+      let _result = self.killer.kill();
+    } else {
+      signal::kill_session(self.pid);
+    }
+  }
+
+  #[cfg(windows)]
+  fn kill(&mut self) {
+    let _result = self.killer.kill();
+  }
+
   fn resize(&self, size: &Size) {
     let rows = size.height;
     let cols = size.width;
@@ -137,25 +208,275 @@ impl Inst {
     if let Ok(mut vt) = self.vt.write() {
       vt.set_size(rows, cols);
     }
+    if let Some(history) = &self.history {
+      history.set_size(rows, cols);
+    }
+  }
+
+  /// The terminal, or the history while it is shown.
+  pub fn vt(&self) -> &VtWrap {
+    match &self.history {
+      Some(history) => &history.vt,
+      None => &self.vt,
+    }
+  }
+
+  pub fn in_history(&self) -> bool {
+    self.history.is_some()
+  }
+
+  /// Block of the archived output that is shown, see `Hit::block`.
+  pub fn history_block(&self) -> Option<usize> {
+    self.history.as_ref().map(|history| history.block())
+  }
+
+  fn scroll_up(&mut self, n: usize) {
+    let archive = match &self.archive {
+      Some(archive) => archive.lock().unwrap(),
+      None => {
+        if let Ok(mut vt) = self.vt.write() {
+          scroll_vt_up(&mut vt, n);
+        }
+        return;
+      }
+    };
+    if let Some(history) = &mut self.history {
+      history.scroll_up(&archive, n);
+    } else if let Ok(mut vt) = self.vt.write() {
+      let screen = vt.screen();
+      let size = screen.size();
+      if screen.scrollback() < screen.scrollback_len() {
+        scroll_vt_up(&mut vt, n);
+      } else {
+        // Older output is in the archive, before the lines that are still
+        // in the terminal.
+        let end_line = archive.lines().saturating_sub(terminal_lines(&mut vt));
+        self.history = HistoryView::open(&archive, end_line, size);
+      }
+    }
+  }
+
+  fn scroll_down(&mut self, n: usize) {
+    if let (Some(history), Some(archive)) = (&mut self.history, &self.archive) {
+      if !history.scroll_down(&archive.lock().unwrap(), n) {
+        self.history = None;
+      }
+    } else if let Ok(mut vt) = self.vt.write() {
+      scroll_vt_down(&mut vt, n);
+    }
+  }
+
+  /// Text of the terminal and its scrollback, after the archived output with
+  /// `scrollback: unlimited`. With `limit`, only the last lines.
+  fn output_lines(&self, limit: Option<usize>) -> Vec<String> {
+    let archive = self.archive.as_ref().map(|archive| archive.lock().unwrap());
+    let mut vt = match self.vt.write() {
+      Ok(vt) => vt,
+      Err(_) => return Vec::new(),
+    };
+    let mut lines = vt_lines(&mut vt);
+    let missing = limit.map(|limit| limit.saturating_sub(lines.len()));
+    if let Some(archive) = archive.filter(|_| missing != Some(0)) {
+      // Same split as in `scroll_up`.
+      let end_line = archive.lines().saturating_sub(terminal_lines(&mut vt));
+      let mut archived = archived_lines(&archive, end_line, missing);
+      archived.append(&mut lines);
+      lines = archived;
+    }
+    if let Some(limit) = limit {
+      lines.drain(..lines.len().saturating_sub(limit));
+    }
+    lines
+  }
+
+  /// Jumps to the next match of the search, see `Search::find`. With
+  /// `scrollback: unlimited`, the archived output is searched too, and the
+  /// block with the match is shown like when scrolling to it.
+  fn find(&mut self, search: &mut Search, backward: bool) -> bool {
+    let archive = match &self.archive {
+      Some(archive) => archive.clone(),
+      None => {
+        return match self.vt.write() {
+          Ok(mut vt) => search.find(&mut vt, backward),
+          Err(_) => false,
+        };
+      }
+    };
+    let archive = archive.lock().unwrap();
+    let (end_line, size) = match self.vt.write() {
+      Ok(mut vt) => {
+        let end_line = match &self.history {
+          Some(history) => history.end_line(),
+          // Same split as in `scroll_up`.
+          None => archive.lines().saturating_sub(terminal_lines(&mut vt)),
+        };
+        (end_line, vt.screen().size())
+      }
+      Err(_) => return false,
+    };
+
+    // The blocks from the top, followed by the terminal.
+    let blocks = archive.blocks_before(end_line);
+    let count = blocks + 1;
+    let current = self.history_block();
+    let index = current.unwrap_or(blocks);
+    for i in 0..=count {
+      // Back at the shown block or terminal after going around.
+      if i == 0 || i == count {
+        if let Ok(mut vt) = self.vt().write() {
+          if search.find_in(&mut vt, backward, current, i > 0) {
+            return true;
+          }
+        }
+        continue;
+      }
+
+      let index = if backward {
+        (index + count - i) % count
+      } else {
+        (index + i) % count
+      };
+      if index == blocks {
+        if let Ok(mut vt) = self.vt.write() {
+          if search.find_in(&mut vt, backward, None, true) {
+            drop(vt);
+            self.history = None;
+            return true;
+          }
+        }
+      } else if search.matches_lines(&block_lines(&archive, index, end_line)) {
+        // Only blocks with a match are replayed.
+        let view = HistoryView::open_block(&archive, index, end_line, size);
+        let found = match view.vt.write() {
+          Ok(mut vt) => search.find_in(&mut vt, backward, Some(index), true),
+          Err(_) => false,
+        };
+        if found {
+          self.history = Some(view);
+          return true;
+        }
+      }
+    }
+    false
+  }
+
+  /// Shows the bottom of the terminal.
+  fn scroll_to_bottom(&mut self) {
+    self.history = None;
+    if let Ok(mut vt) = self.vt.write() {
+      if vt.screen().scrollback() > 0 {
+        vt.set_scrollback(0);
+      }
+    }
+  }
+}
+
+/// Number of rows in the scrollback. `Screen::scrollback_len` is the number
+/// of rows it can hold.
+pub fn scrollback_rows(vt: &mut vt100::Parser) -> usize {
+  let offset = vt.screen().scrollback();
+  vt.set_scrollback(usize::MAX);
+  let rows = vt.screen().scrollback();
+  vt.set_scrollback(offset);
+  rows
+}
+
+fn scroll_vt_up(vt: &mut vt100::Parser, n: usize) {
+  let pos = usize::saturating_add(vt.screen().scrollback(), n);
+  vt.set_scrollback(pos);
+}
+
+fn scroll_vt_down(vt: &mut vt100::Parser, n: usize) {
+  let pos = usize::saturating_sub(vt.screen().scrollback(), n);
+  vt.set_scrollback(pos);
+}
+
+/// Lines of the terminal and its scrollback, with wrapped rows joined. Empty
+/// rows at the bottom of the screen are left out.
+fn vt_lines(vt: &mut vt100::Parser) -> Vec<String> {
+  let len = scrollback_rows(vt);
+  let screen = vt.screen();
+  let scrollback = screen.scrollback();
+  let (rows, cols) = screen.size();
+  let total = len + rows as usize;
+
+  let mut lines = Vec::new();
+  let mut line = String::new();
+  let mut row = 0;
+  // Rows of the scrollback are only reachable by scrolling to them.
+  while row < total {
+    let offset = len.saturating_sub(row);
+    vt.set_scrollback(offset);
+    let first = len - offset;
+    let screen = vt.screen();
+    // `rows` yields rows above the screen when scrolled further than its
+    // height.
+    let visible_rows = screen.rows(0, cols).take(rows as usize);
+    for (visible, text) in visible_rows.enumerate().skip(row - first) {
+      line.push_str(&text);
+      if !screen.row_wrapped(visible as u16) {
+        lines.push(std::mem::take(&mut line));
+      }
+    }
+    row = first + rows as usize;
+  }
+  if !line.is_empty() {
+    lines.push(line);
   }
+
+  vt.set_scrollback(scrollback);
+  while matches!(lines.last(), Some(line) if line.is_empty()) {
+    lines.pop();
+  }
+  lines
 }
 
 pub struct Proc {
   pub id: usize,
   pub name: String,
   pub to_restart: bool,
+  /// Removed from the config. Dropped from the list once it's down.
+  pub to_remove: bool,
+  pub exit_status: Option<ExitStatus>,
+  pub depends_on: Vec<String>,
+  pub tags: Vec<String>,
+  /// Autostart is postponed until dependencies are ready.
+  pub waiting_for_deps: bool,
+  ready_probe: Option<ReadyProbe>,
+  /// `ready_when` conditions were met by the current instance.
+  pub ready: bool,
   pub changed: bool,
   pub cmd: CommandBuilder,
   size: Size,
 
   stop_signal: StopSignal,
+  stop_timeout: Duration,
+  stop_deadline: Option<Instant>,
+
+  restart: RestartConfig,
+  restart_attempts: usize,
+  restart_at: Option<Instant>,
+  stop_requested: bool,
+  started_at: Option<Instant>,
+
+  watcher: Option<FileWatcher>,
+  output: Vec<SharedSink>,
+  log: Option<SharedSink>,
+  scrollback: Scrollback,
 
   pub tx: UnboundedSender<(usize, ProcUpdate)>,
 
   pub inst: ProcState,
   pub copy_mode: CopyMode,
+  pub search: Option<Search>,
 }
 
+const READER_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Scrollback of the terminal with `scrollback: unlimited`. Older output is
+/// read from the archive.
+const LIVE_SCROLLBACK: usize = 1000;
+
 static NEXT_PROC_ID: AtomicUsize = AtomicUsize::new(1);
 
 #[derive(Debug)]
@@ -168,17 +489,115 @@ pub enum ProcState {
 #[derive(Debug)]
 pub enum ProcUpdate {
   Render,
-  Stopped,
+  Stopped(ExitStatus),
   Started,
+  Ready,
+  AutoRestart,
+  StopTimer,
+  FilesChanged,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExitStatus {
+  /// Exit code, if the process exited normally.
+  pub code: Option<u32>,
+  /// Signal that terminated the process. Always `None` on Windows.
+  pub signal: Option<i32>,
+}
+
+impl ExitStatus {
+  const UNKNOWN: ExitStatus = ExitStatus {
+    code: None,
+    signal: None,
+  };
+
+  fn from_success(success: bool) -> Self {
+    ExitStatus {
+      code: success.then_some(0),
+      signal: None,
+    }
+  }
+
+  pub fn success(&self) -> bool {
+    self.code == Some(0)
+  }
+
+  /// Exit code for mprocs itself. Signals are reported the way shells do.
+  pub fn exit_code(&self) -> i32 {
+    match (self.code, self.signal) {
+      (Some(code), _) => code as i32,
+      (None, Some(signal)) => 128 + signal,
+      (None, None) => 1,
+    }
+  }
+}
+
+/// Waits for the process to exit without reaping it, see `signal::reap`.
+#[cfg(not(windows))]
+fn wait_child(mut child: Box<dyn Child + Send + Sync>, pid: u32) -> ExitStatus {
+  if pid == 0 {
+    // `waitid` needs a pid, fall back to portable-pty.
+    let success = matches!(child.wait(), Ok(status) if status.success());
+    return ExitStatus::from_success(success);
+  }
+
+  let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+  loop {
+    let ret = unsafe {
+      libc::waitid(
+        libc::P_PID,
+        pid as libc::id_t,
+        &mut info,
+        libc::WEXITED | libc::WNOWAIT,
+      )
+    };
+    if ret == 0 {
+      break;
+    }
+    let err = std::io::Error::last_os_error();
+    if err.kind() == std::io::ErrorKind::Interrupted {
+      continue;
+    }
+    log::warn!("waitid({}) failed: {}", pid, err);
+    return ExitStatus::UNKNOWN;
+  }
+
+  let status = unsafe { info.si_status() };
+  match info.si_code {
+    libc::CLD_EXITED => ExitStatus {
+      code: Some(status as u32),
+      signal: None,
+    },
+    libc::CLD_KILLED | libc::CLD_DUMPED => ExitStatus {
+      code: None,
+      signal: Some(status),
+    },
+    _ => ExitStatus::UNKNOWN,
+  }
+}
+
+#[cfg(windows)]
+fn wait_child(
+  mut child: Box<dyn Child + Send + Sync>,
+  _pid: u32,
+) -> ExitStatus {
+  // portable-pty only tells whether the process succeeded on Windows.
+  let success = matches!(child.wait(), Ok(status) if status.success());
+  ExitStatus::from_success(success)
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// A process that stayed up at least this long is considered healthy, and the
+/// automatic restart counter is reset when it exits.
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 #[allow(clippy::upper_case_acronyms)]
 pub enum StopSignal {
   #[serde(rename = "SIGINT")]
   SIGINT,
   #[serde(rename = "SIGTERM")]
+  #[default]
   SIGTERM,
   #[serde(rename = "SIGKILL")]
   SIGKILL,
@@ -186,18 +605,21 @@ pub enum StopSignal {
   HardKill,
 }
 
-impl Default for StopSignal {
-  fn default() -> Self {
-    StopSignal::SIGTERM
-  }
+fn log_writer(cfg: &ProcConfig) -> Option<SharedSink> {
+  cfg
+    .log
+    .as_ref()
+    .map(|log| Arc::new(Mutex::new(LogWriter::new(log))) as SharedSink)
 }
 
 impl Proc {
-  pub fn new(
+  /// Creates a process whose output is also written to `output`.
+  pub fn with_output(
     name: String,
     cfg: &ProcConfig,
     tx: UnboundedSender<(usize, ProcUpdate)>,
     size: Rect,
+    output: Vec<SharedSink>,
   ) -> Self {
     let id = NEXT_PROC_ID.fetch_add(1, Ordering::Relaxed);
     let size = Size::new(size);
@@ -205,43 +627,232 @@ impl Proc {
       id,
       name,
       to_restart: false,
+      to_remove: false,
+      exit_status: None,
+      depends_on: cfg.depends_on.clone(),
+      tags: cfg.tags.clone(),
+      waiting_for_deps: false,
+      ready_probe: ReadyProbe::new(cfg),
+      ready: false,
       changed: false,
       cmd: cfg.into(),
       size,
 
       stop_signal: cfg.stop.clone(),
+      stop_timeout: cfg.stop_timeout,
+      stop_deadline: None,
+
+      restart: cfg.restart.clone(),
+      restart_attempts: 0,
+      restart_at: None,
+      stop_requested: false,
+      started_at: None,
+
+      watcher: None,
+      output,
+      log: log_writer(cfg),
+      scrollback: cfg.scrollback,
 
       tx,
 
       inst: ProcState::None,
       copy_mode: CopyMode::None(None),
+      search: None,
     };
 
+    proc.watcher = proc.spawn_watcher(cfg);
+
     if cfg.autostart {
-      proc.spawn_new_inst();
+      if cfg.depends_on.is_empty() {
+        proc.spawn_new_inst();
+      } else {
+        proc.waiting_for_deps = true;
+      }
     }
 
     proc
   }
 
+  /// Applies a reloaded config. Takes effect on the next start.
+  pub fn update_config(&mut self, cfg: &ProcConfig) {
+    self.cmd = cfg.into();
+    self.depends_on = cfg.depends_on.clone();
+    self.tags = cfg.tags.clone();
+    for sink in &self.output {
+      sink.lock().unwrap().update_config(cfg);
+    }
+    self.ready_probe = ReadyProbe::new(cfg);
+    self.stop_signal = cfg.stop.clone();
+    self.stop_timeout = cfg.stop_timeout;
+    self.restart = cfg.restart.clone();
+    self.watcher = self.spawn_watcher(cfg);
+    self.log = log_writer(cfg);
+    self.scrollback = cfg.scrollback;
+  }
+
+  fn spawn_watcher(&self, cfg: &ProcConfig) -> Option<FileWatcher> {
+    cfg.watch.as_ref().map(|watch| {
+      FileWatcher::spawn(self.id, watch, cfg.cwd.as_ref(), self.tx.clone())
+    })
+  }
+
   fn spawn_new_inst(&mut self) {
     assert_matches!(self.inst, ProcState::None);
 
-    let spawned =
-      Inst::spawn(self.id, self.cmd.clone(), self.tx.clone(), &self.size);
+    let log_signal = Arc::new(LogSignal::default());
+    let log_watch = self
+      .ready_probe
+      .as_ref()
+      .and_then(|probe| probe.log_watch(&log_signal));
+    let spawned = Inst::spawn(
+      self.id,
+      self.cmd.clone(),
+      self.tx.clone(),
+      &self.size,
+      log_watch,
+      self.output.iter().chain(&self.log).cloned().collect(),
+      self.scrollback,
+    );
     let inst = match spawned {
-      Ok(inst) => ProcState::Some(inst),
+      Ok(inst) => {
+        if let Some(probe) = &self.ready_probe {
+          probe.spawn(
+            self.id,
+            inst.running.clone(),
+            log_signal,
+            self.tx.clone(),
+          );
+        }
+        ProcState::Some(inst)
+      }
       Err(err) => ProcState::Error(err.to_string()),
     };
     self.inst = inst;
+    self.started_at = Some(Instant::now());
   }
 
   pub fn start(&mut self) {
     if !self.is_up() {
-      self.inst = ProcState::None;
-      self.spawn_new_inst();
+      self.restart_attempts = 0;
+      self.respawn();
+    }
+  }
+
+  /// Starts the process, or postpones the start until its dependencies are
+  /// ready.
+  pub fn start_or_wait(&mut self) {
+    if self.depends_on.is_empty() {
+      self.start();
+    } else {
+      self.waiting_for_deps = true;
+    }
+  }
+
+  /// Stops the process and starts it again once it is down. Starts it right
+  /// away if it is not running.
+  pub fn restart(&mut self) {
+    if self.is_up() {
+      self.stop();
+      self.to_restart = true;
+    } else {
+      self.start();
+    }
+  }
 
-      let _res = self.tx.send((self.id, ProcUpdate::Started));
+  /// Restarts the process after its watched files changed. Processes that
+  /// were stopped, or never started, stay down.
+  pub fn restart_on_change(&mut self) {
+    let started = !matches!(self.inst, ProcState::None);
+    if self.is_up() || (started && !self.stop_requested) {
+      self.restart();
+    }
+  }
+
+  fn respawn(&mut self) {
+    self.waiting_for_deps = false;
+    self.stop_requested = false;
+    self.restart_at = None;
+    self.exit_status = None;
+    self.ready = false;
+    self.stop_deadline = None;
+    self.inst = ProcState::None;
+    self.spawn_new_inst();
+
+    let _res = self.tx.send((self.id, ProcUpdate::Started));
+  }
+
+  /// Called when the process has exited on its own or after a stop request.
+  /// Schedules an automatic restart according to the restart policy.
+  pub fn schedule_auto_restart(&mut self, success: bool) {
+    if self.stop_requested {
+      return;
+    }
+    let restart = match self.restart.policy {
+      RestartPolicy::Never => false,
+      RestartPolicy::OnFailure => !success,
+      RestartPolicy::Always => true,
+    };
+    if !restart {
+      return;
+    }
+
+    if let Some(started_at) = self.started_at {
+      if started_at.elapsed() >= RESTART_RESET_AFTER {
+        self.restart_attempts = 0;
+      }
+    }
+    if let Some(max_retries) = self.restart.max_retries {
+      if self.restart_attempts >= max_retries {
+        log::warn!(
+          "Process \"{}\" exited {} times in a row. Not restarting.",
+          self.name,
+          self.restart_attempts + 1,
+        );
+        return;
+      }
+    }
+
+    let delay = self.restart.delay_for(self.restart_attempts);
+    self.restart_attempts += 1;
+    self.restart_at = Some(Instant::now() + delay);
+
+    let id = self.id;
+    let tx = self.tx.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(delay).await;
+      let _res = tx.send((id, ProcUpdate::AutoRestart));
+    });
+  }
+
+  /// Handles a timer scheduled by `schedule_auto_restart`. Timers that were
+  /// cancelled or superseded by a later schedule are ignored.
+  pub fn auto_restart(&mut self) {
+    match self.restart_at {
+      Some(restart_at) if Instant::now() >= restart_at && !self.is_up() => {
+        self.respawn();
+      }
+      _ => (),
+    }
+  }
+
+  /// An automatic restart is scheduled.
+  pub fn restart_pending(&self) -> bool {
+    self.restart_at.is_some()
+  }
+
+  /// The last stop of the process was requested by mprocs or the user.
+  pub fn stop_requested(&self) -> bool {
+    self.stop_requested
+  }
+
+  /// Stops a running process, or cancels its pending start.
+  pub fn stop_or_cancel(&mut self) {
+    if self.is_up() {
+      self.stop();
+    } else {
+      self.waiting_for_deps = false;
+      self.restart_at = None;
+      self.to_restart = false;
     }
   }
 
@@ -253,36 +864,51 @@ impl Proc {
     }
   }
 
+  /// Running and, if `ready_when` is configured, ready.
+  pub fn is_ready(&self) -> bool {
+    self.is_up() && (self.ready_probe.is_none() || self.ready)
+  }
+
+  pub fn has_ready_probe(&self) -> bool {
+    self.ready_probe.is_some()
+  }
+
   pub fn lock_vt(
     &self,
   ) -> Option<std::sync::RwLockReadGuard<'_, vt100::Parser>> {
     match &self.inst {
       ProcState::None => None,
-      ProcState::Some(inst) => inst.vt.read().ok(),
-      ProcState::Error(_) => None,
-    }
-  }
-
-  pub fn lock_vt_mut(
-    &mut self,
-  ) -> Option<std::sync::RwLockWriteGuard<'_, vt100::Parser>> {
-    match &self.inst {
-      ProcState::None => None,
-      ProcState::Some(inst) => inst.vt.write().ok(),
+      ProcState::Some(inst) => inst.vt().read().ok(),
       ProcState::Error(_) => None,
     }
   }
 
   pub fn kill(&mut self) {
+    self.waiting_for_deps = false;
+    self.restart_at = None;
+    self.stop_requested = true;
     if self.is_up() {
       if let ProcState::Some(inst) = &mut self.inst {
-        let _result = inst.killer.kill();
+        inst.kill();
       }
     }
   }
 
+  /// The process has exited, but its children may be still alive. Kills
+  /// them, so they don't outlive mprocs. The process is not reaped while
+  /// `inst` exists, so its session id still belongs to it.
+  pub fn kill_orphans(&mut self) {
+    #[cfg(not(windows))]
+    if let ProcState::Some(inst) = &self.inst {
+      signal::kill_session(inst.pid);
+    }
+  }
+
   #[cfg(not(windows))]
   pub fn stop(&mut self) {
+    self.waiting_for_deps = false;
+    self.restart_at = None;
+    self.stop_requested = true;
     match self.stop_signal.clone() {
       StopSignal::SIGINT => self.send_signal(libc::SIGINT),
       StopSignal::SIGTERM => self.send_signal(libc::SIGTERM),
@@ -294,10 +920,14 @@ impl Proc {
       }
       StopSignal::HardKill => self.kill(),
     }
+    self.start_stop_timer();
   }
 
   #[cfg(windows)]
   pub fn stop(&mut self) {
+    self.waiting_for_deps = false;
+    self.restart_at = None;
+    self.stop_requested = true;
     match self.stop_signal.clone() {
       StopSignal::SIGINT => log::warn!("SIGINT signal is ignored on Windows"),
       StopSignal::SIGTERM => self.kill(),
@@ -309,12 +939,109 @@ impl Proc {
       }
       StopSignal::HardKill => self.kill(),
     }
+    self.start_stop_timer();
+  }
+
+  /// Starts counting down `stop_timeout` after a stop request. Sends
+  /// `ProcUpdate::StopTimer` every second, so the countdown can be rendered.
+  fn start_stop_timer(&mut self) {
+    if self.stop_deadline.is_some() || !self.is_up() {
+      return;
+    }
+    let running = match &self.inst {
+      ProcState::Some(inst) => inst.running.clone(),
+      ProcState::None | ProcState::Error(_) => return,
+    };
+
+    let deadline = Instant::now() + self.stop_timeout;
+    self.stop_deadline = Some(deadline);
+
+    let id = self.id;
+    let tx = self.tx.clone();
+    tokio::spawn(async move {
+      loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        tokio::time::sleep(remaining.min(Duration::from_secs(1))).await;
+        if !running.load(Ordering::Relaxed) {
+          return;
+        }
+        let _res = tx.send((id, ProcUpdate::StopTimer));
+        if Instant::now() >= deadline {
+          return;
+        }
+      }
+    });
+  }
+
+  pub fn pid(&self) -> Option<u32> {
+    match &self.inst {
+      ProcState::Some(inst) if self.is_up() => Some(inst.pid),
+      _ => None,
+    }
+  }
+
+  /// Time since the running instance was started.
+  pub fn uptime(&self) -> Option<Duration> {
+    match self.started_at {
+      Some(started_at) if self.is_up() => Some(started_at.elapsed()),
+      _ => None,
+    }
+  }
+
+  /// Status for remote control clients, like in the process list.
+  pub fn status(&self) -> &'static str {
+    if self.stop_time_left().is_some() {
+      "stopping"
+    } else if self.is_up() && self.ready {
+      "ready"
+    } else if self.is_up() {
+      "up"
+    } else if self.waiting_for_deps {
+      "waiting"
+    } else if self.restart_pending() {
+      "restarting"
+    } else if matches!(self.inst, ProcState::Error(_)) {
+      "error"
+    } else {
+      "down"
+    }
+  }
+
+  /// Text of the output, see `Inst::output_lines`.
+  pub fn output_lines(&self, limit: Option<usize>) -> Vec<String> {
+    match &self.inst {
+      ProcState::Some(inst) => inst.output_lines(limit),
+      ProcState::None | ProcState::Error(_) => Vec::new(),
+    }
+  }
+
+  /// Time left until the process is killed, if it is being stopped with a
+  /// timeout.
+  pub fn stop_time_left(&self) -> Option<Duration> {
+    match self.stop_deadline {
+      Some(deadline) if self.is_up() => {
+        Some(deadline.saturating_duration_since(Instant::now()))
+      }
+      _ => None,
+    }
+  }
+
+  /// Kills the process if it didn't stop within `stop_timeout`.
+  pub fn check_stop_timeout(&mut self) {
+    if self.stop_time_left() == Some(Duration::ZERO) {
+      log::warn!(
+        "Process \"{}\" did not stop in time. Killing it.",
+        self.name
+      );
+      self.stop_deadline = None;
+      self.kill();
+    }
   }
 
   #[cfg(not(windows))]
   fn send_signal(&mut self, sig: libc::c_int) {
     if let ProcState::Some(inst) = &self.inst {
-      unsafe { libc::kill(inst.pid as i32, sig) };
+      signal::signal_group(inst.pid, sig);
     }
   }
 
@@ -330,7 +1057,7 @@ impl Proc {
     if self.is_up() {
       let application_cursor_keys = self
         .lock_vt()
-        .map_or(false, |vt| vt.screen().application_cursor());
+        .is_some_and(|vt| vt.screen().application_cursor());
       let encoder = encode_key(
         key,
         KeyCodeEncodeModes {
@@ -352,12 +1079,8 @@ impl Proc {
 
   pub fn write_all(&mut self, bytes: &[u8]) {
     if self.is_up() {
-      if let Some(mut vt) = self.lock_vt_mut() {
-        if vt.screen().scrollback() > 0 {
-          vt.set_scrollback(0);
-        }
-      }
       if let ProcState::Some(inst) = &mut self.inst {
+        inst.scroll_to_bottom();
         inst.master.write_all(bytes).log_ignore();
       }
     }
@@ -366,8 +1089,8 @@ impl Proc {
   pub fn scroll_up_lines(&mut self, n: usize) {
     match &mut self.copy_mode {
       CopyMode::None(_) => {
-        if let Some(mut vt) = self.lock_vt_mut() {
-          Self::scroll_vt_up(&mut vt, n);
+        if let ProcState::Some(inst) = &mut self.inst {
+          inst.scroll_up(n);
         }
       }
       CopyMode::Start(screen, _) | CopyMode::Range(screen, _, _) => {
@@ -376,11 +1099,6 @@ impl Proc {
     }
   }
 
-  fn scroll_vt_up(vt: &mut vt100::Parser, n: usize) {
-    let pos = usize::saturating_add(vt.screen().scrollback(), n);
-    vt.set_scrollback(pos);
-  }
-
   fn scroll_screen_up(screen: &mut vt100::Screen, n: usize) {
     let pos = usize::saturating_add(screen.scrollback(), n);
     screen.set_scrollback(pos);
@@ -389,8 +1107,8 @@ impl Proc {
   pub fn scroll_down_lines(&mut self, n: usize) {
     match &mut self.copy_mode {
       CopyMode::None(_) => {
-        if let Some(mut vt) = self.lock_vt_mut() {
-          Self::scroll_vt_down(&mut vt, n);
+        if let ProcState::Some(inst) = &mut self.inst {
+          inst.scroll_down(n);
         }
       }
       CopyMode::Start(screen, _) | CopyMode::Range(screen, _, _) => {
@@ -399,16 +1117,20 @@ impl Proc {
     }
   }
 
-  fn scroll_vt_down(vt: &mut vt100::Parser, n: usize) {
-    let pos = usize::saturating_sub(vt.screen().scrollback(), n);
-    vt.set_scrollback(pos);
-  }
-
   fn scroll_screen_down(screen: &mut vt100::Screen, n: usize) {
     let pos = usize::saturating_sub(screen.scrollback(), n);
     screen.set_scrollback(pos);
   }
 
+  /// Jumps to the next match of the search, see `Inst::find`.
+  pub fn find_next(&mut self, backward: bool) {
+    if let (Some(search), ProcState::Some(inst)) =
+      (&mut self.search, &mut self.inst)
+    {
+      inst.find(search, backward);
+    }
+  }
+
   pub fn scroll_half_screen_up(&mut self) {
     self.scroll_up_lines(self.size.height as usize / 2);
   }
@@ -491,7 +1213,7 @@ impl Proc {
         MouseProtocolMode::None => match event.kind {
           MouseEventKind::Down(btn) => match btn {
             MouseButton::Left => {
-              if let Some(vt) = inst.vt.read().log_get() {
+              if let Some(vt) = inst.vt().read().log_get() {
                 self.copy_mode = CopyMode::None(Some(translate_mouse_pos(
                   &event,
                   &term_area,
@@ -503,7 +1225,7 @@ impl Proc {
           },
           MouseEventKind::Up(_) => (),
           MouseEventKind::Drag(MouseButton::Left) => {
-            if let Some(vt) = inst.vt.read().log_get() {
+            if let Some(vt) = inst.vt().read().log_get() {
               let pos = translate_mouse_pos(
                 &event,
                 &term_area,
@@ -524,14 +1246,10 @@ impl Proc {
           MouseEventKind::Drag(_) => (),
           MouseEventKind::Moved => (),
           MouseEventKind::ScrollDown => {
-            if let Some(mut vt) = inst.vt.write().log_get() {
-              Self::scroll_vt_down(&mut vt, config.mouse_scroll_speed);
-            }
+            inst.scroll_down(config.mouse_scroll_speed);
           }
           MouseEventKind::ScrollUp => {
-            if let Some(mut vt) = inst.vt.write().log_get() {
-              Self::scroll_vt_up(&mut vt, config.mouse_scroll_speed);
-            }
+            inst.scroll_up(config.mouse_scroll_speed);
           }
         },
         MouseProtocolMode::Press
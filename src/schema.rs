@@ -0,0 +1,349 @@
+//! JSON Schema of the config file, printed by `--print-schema`. Fields come
+//! from the lists that config validation uses, so a field can't be added to
+//! the config without a schema.
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+  config::{
+    CONFIG_FIELDS, INHERITED_FIELDS, LOG_FIELDS, ON_EXIT_FIELDS, PROC_FIELDS,
+    READY_FIELDS, RESTART_FIELDS, WATCH_FIELDS,
+  },
+  event::{AppEvent, CopyMove},
+};
+
+pub fn config_schema() -> Value {
+  json!({
+    "$schema": "http://json-schema.org/draft-07/schema#",
+    "title": "mprocs config",
+    "type": "object",
+    "properties": object_properties(&CONFIG_FIELDS, config_field),
+    "additionalProperties": false,
+    "definitions": {
+      "select": select_schema(),
+      "proc": {
+        "type": "object",
+        "properties": object_properties(&PROC_FIELDS, proc_field),
+        "additionalProperties": false,
+      },
+      "event": event_schema(),
+      "keymap": keymap_schema(),
+    },
+  })
+}
+
+/// Any value in the config can be replaced with a `$select` object.
+fn selectable(schema: Value) -> Value {
+  json!({ "anyOf": [schema, { "$ref": "#/definitions/select" }] })
+}
+
+fn object_properties(fields: &[&str], schema: fn(&str) -> Value) -> Value {
+  let props = fields
+    .iter()
+    .map(|field| (field.to_string(), selectable(schema(field))))
+    .collect::<Map<_, _>>();
+  Value::Object(props)
+}
+
+fn select_schema() -> Value {
+  json!({
+    "description": "Value depending on the operating system.",
+    "type": "object",
+    "properties": { "$select": { "const": "os" } },
+    "required": ["$select"],
+  })
+}
+
+fn string_or_array() -> Value {
+  json!({
+    "anyOf": [
+      { "type": "string" },
+      { "type": "array", "items": { "type": "string" } },
+    ],
+  })
+}
+
+fn duration() -> Value {
+  json!({
+    "description": "Seconds or a string with a unit: 300ms, 5s, 2m, 1h.",
+    "anyOf": [
+      { "type": "number", "minimum": 0 },
+      { "type": "string", "pattern": "^\\s*[0-9.]+\\s*(ms|s|m|h)?\\s*$" },
+    ],
+  })
+}
+
+fn size() -> Value {
+  json!({
+    "description": "Bytes or a string with a unit: 512KB, 10MB, 1GB.",
+    "anyOf": [
+      { "type": "integer", "minimum": 0 },
+      { "type": "string", "pattern": "^\\s*[0-9]+\\s*([kKmMgG]?[bB]?)\\s*$" },
+    ],
+  })
+}
+
+fn scrollback() -> Value {
+  json!({
+    "description": "Number of lines, or \"unlimited\" to keep all output.",
+    "anyOf": [
+      { "type": "integer", "minimum": 0 },
+      { "const": "unlimited" },
+    ],
+  })
+}
+
+fn config_field(field: &str) -> Value {
+  match field {
+    "procs" => json!({
+      "type": "object",
+      "additionalProperties": {
+        "anyOf": [
+          { "$ref": "#/definitions/proc" },
+          { "type": "string", "description": "Shell command." },
+          { "type": "array", "items": { "type": "string" } },
+          { "type": "null" },
+          { "$ref": "#/definitions/select" },
+        ],
+      },
+    }),
+    "templates" => json!({
+      "type": "object",
+      "additionalProperties": {
+        "type": "object",
+        "properties": object_properties(&INHERITED_FIELDS, proc_field),
+        "additionalProperties": false,
+      },
+    }),
+    "profiles" => json!({
+      "type": "object",
+      "additionalProperties": { "type": "array", "items": { "type": "string" } },
+    }),
+    "on_exit" => json!({
+      "type": "object",
+      "properties": object_properties(&ON_EXIT_FIELDS, on_exit_field),
+      "additionalProperties": false,
+    }),
+    "include" => json!({ "type": "array", "items": { "type": "string" } }),
+    "server" => {
+      json!({ "type": "string", "description": "HOST:PORT or unix:PATH" })
+    }
+    "log" => log_schema(),
+    "scrollback" => scrollback(),
+    "port_offset" | "mouse_scroll_speed" | "proc_list_width" => {
+      json!({ "type": "integer", "minimum": 0 })
+    }
+    "hide_keymap_window" => json!({ "type": "boolean" }),
+    "keymap_procs" | "keymap_term" | "keymap_copy" => {
+      json!({ "$ref": "#/definitions/keymap" })
+    }
+    _ => panic!("No schema for config field \"{}\".", field),
+  }
+}
+
+fn proc_field(field: &str) -> Value {
+  match field {
+    "shell" | "cwd" | "extends" => json!({ "type": "string" }),
+    "cmd" | "depends_on" | "tags" => {
+      json!({ "type": "array", "items": { "type": "string" } })
+    }
+    "env" => json!({
+      "type": "object",
+      "additionalProperties": { "type": ["string", "null"] },
+    }),
+    "env_file" | "add_path" => string_or_array(),
+    "autostart" => json!({ "type": "boolean" }),
+    "stop" => json!({
+      "anyOf": [
+        { "enum": ["SIGINT", "SIGTERM", "SIGKILL", "hard-kill"] },
+        {
+          "type": "object",
+          "properties": {
+            "send-keys": { "type": "array", "items": { "type": "string" } },
+          },
+          "required": ["send-keys"],
+          "additionalProperties": false,
+        },
+      ],
+    }),
+    "stop_timeout" => duration(),
+    "restart" => json!({
+      "anyOf": [
+        restart_policy(),
+        {
+          "type": "object",
+          "properties": object_properties(&RESTART_FIELDS, restart_field),
+          "additionalProperties": false,
+        },
+      ],
+    }),
+    "ready_when" => json!({
+      "type": "object",
+      "properties": object_properties(&READY_FIELDS, ready_field),
+      "additionalProperties": false,
+    }),
+    "watch" => json!({
+      "anyOf": [
+        string_or_array(),
+        {
+          "type": "object",
+          "properties": object_properties(&WATCH_FIELDS, watch_field),
+          "required": ["paths"],
+          "additionalProperties": false,
+        },
+      ],
+    }),
+    "log" => log_schema(),
+    "scrollback" => scrollback(),
+    _ => panic!("No schema for proc field \"{}\".", field),
+  }
+}
+
+fn restart_policy() -> Value {
+  json!({ "enum": ["never", "on-failure", "always"] })
+}
+
+fn restart_field(field: &str) -> Value {
+  match field {
+    "policy" => restart_policy(),
+    "max_retries" => json!({ "type": "integer", "minimum": 0 }),
+    "delay" | "max_delay" => duration(),
+    _ => panic!("No schema for restart field \"{}\".", field),
+  }
+}
+
+fn ready_field(field: &str) -> Value {
+  match field {
+    "log" | "cmd" => json!({ "type": "string" }),
+    "port" => json!({ "type": ["integer", "string"] }),
+    "interval" => duration(),
+    _ => panic!("No schema for ready_when field \"{}\".", field),
+  }
+}
+
+fn watch_field(field: &str) -> Value {
+  match field {
+    "paths" | "ignore" => string_or_array(),
+    "debounce" => duration(),
+    _ => panic!("No schema for watch field \"{}\".", field),
+  }
+}
+
+fn log_schema() -> Value {
+  json!({
+    "anyOf": [
+      { "type": "boolean" },
+      { "type": "string", "description": "Log file path." },
+      {
+        "type": "object",
+        "properties": object_properties(&LOG_FIELDS, log_field),
+        "additionalProperties": false,
+      },
+    ],
+  })
+}
+
+fn log_field(field: &str) -> Value {
+  match field {
+    "file" => json!({ "type": "string" }),
+    "rotate" => size(),
+    "keep" => json!({ "type": "integer", "minimum": 0 }),
+    "strip_ansi" => json!({ "type": "boolean" }),
+    _ => panic!("No schema for log field \"{}\".", field),
+  }
+}
+
+fn exit_action() -> Value {
+  json!({ "enum": ["stop-all", "quit", "quit-with-code"] })
+}
+
+fn on_exit_field(field: &str) -> Value {
+  match field {
+    "any_fails" => exit_action(),
+    "all_done" => json!({ "const": "quit" }),
+    "proc" => {
+      json!({ "type": "object", "additionalProperties": exit_action() })
+    }
+    _ => panic!("No schema for on_exit field \"{}\".", field),
+  }
+}
+
+/// One alternative per command. Argument types are taken from the
+/// serialized placeholder values.
+fn event_schema() -> Value {
+  let commands = AppEvent::all()
+    .iter()
+    .map(|event| {
+      let value = serde_json::to_value(event).unwrap();
+      let mut props = Map::new();
+      let mut required = Vec::new();
+      for (key, value) in value.as_object().unwrap() {
+        let schema = match (key.as_str(), value) {
+          ("c", value) => json!({ "const": value }),
+          ("dir", _) => json!({ "enum": CopyMove::ALL }),
+          (_, Value::Number(_)) => json!({ "type": "integer", "minimum": 0 }),
+          (_, Value::Array(_)) => {
+            json!({ "type": "array", "items": { "$ref": "#/definitions/event" } })
+          }
+          _ => json!({ "type": "string" }),
+        };
+        props.insert(key.clone(), schema);
+        // Commands acting on processes default to the selected one.
+        if key != "proc" {
+          required.push(key.clone());
+        }
+      }
+      let mut schema = json!({
+        "type": "object",
+        "properties": props,
+        "required": required,
+        "additionalProperties": false,
+      });
+      // Descriptions of commands with arguments mention placeholder values.
+      if required.len() == 1 {
+        let event: AppEvent =
+          serde_json::from_value(json!({ "c": value["c"] })).unwrap();
+        schema["description"] = Value::from(event.desc());
+      }
+      schema
+    })
+    .collect::<Vec<_>>();
+  json!({ "oneOf": commands })
+}
+
+fn keymap_schema() -> Value {
+  json!({
+    "type": "object",
+    "description": "Keys like \"<C-a>\" mapped to commands. null unbinds a key.",
+    "properties": {
+      "reset": {
+        "type": "boolean",
+        "description": "Remove default bindings.",
+      },
+    },
+    "additionalProperties": {
+      "anyOf": [{ "$ref": "#/definitions/event" }, { "type": "null" }],
+    },
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn schema_covers_all_fields() {
+    let schema = config_schema();
+    assert_eq!(
+      schema["properties"].as_object().unwrap().len(),
+      CONFIG_FIELDS.len()
+    );
+    assert_eq!(
+      schema["definitions"]["event"]["oneOf"]
+        .as_array()
+        .unwrap()
+        .len(),
+      AppEvent::all().len()
+    );
+  }
+}
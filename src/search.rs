@@ -0,0 +1,235 @@
+//! Searching the output of a process in its terminal and scrollback.
+
+use crate::proc::scrollback_rows;
+
+pub struct Search {
+  query: String,
+  ignore_case: bool,
+  /// The match that was jumped to last.
+  pub hit: Option<Hit>,
+}
+
+/// Columns `start..end` of a row. `y` is counted from the top of the screen,
+/// rows of the scrollback are negative, like in copy mode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hit {
+  /// Block of the archived output with the match, replayed into a separate
+  /// terminal. `None` for the terminal of the process.
+  pub block: Option<usize>,
+  pub y: i32,
+  pub start: u16,
+  pub end: u16,
+}
+
+impl Search {
+  /// Returns `None` for an empty query. The text is matched ignoring case
+  /// unless it contains an uppercase letter.
+  pub fn new(query: &str) -> Option<Self> {
+    if query.is_empty() {
+      return None;
+    }
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let query = if ignore_case {
+      query.to_lowercase()
+    } else {
+      query.to_string()
+    };
+    Some(Search {
+      query,
+      ignore_case,
+      hit: None,
+    })
+  }
+
+  /// Column ranges of matches in a visible row.
+  pub fn row_matches(
+    &self,
+    screen: &vt100::Screen,
+    row: u16,
+  ) -> Vec<(u16, u16)> {
+    let (_, cols) = screen.size();
+    let mut text = String::new();
+    // Column of every byte of `text`.
+    let mut columns = Vec::new();
+    for col in 0..cols {
+      let cell = match screen.cell(row, col) {
+        Some(cell) => cell,
+        None => break,
+      };
+      if cell.is_wide_continuation() {
+        continue;
+      }
+      let contents = if !cell.has_contents() {
+        " ".to_string()
+      } else if self.ignore_case {
+        cell.contents().to_lowercase()
+      } else {
+        cell.contents()
+      };
+      columns.resize(columns.len() + contents.len(), col);
+      text.push_str(&contents);
+    }
+
+    text
+      .match_indices(&self.query)
+      .map(|(pos, found)| {
+        let start = columns[pos];
+        let end = columns.get(pos + found.len()).copied().unwrap_or(cols);
+        (start, end)
+      })
+      .collect()
+  }
+
+  /// Whether one of `lines` contains a match.
+  pub fn matches_lines(&self, lines: &[String]) -> bool {
+    lines.iter().any(|line| {
+      if self.ignore_case {
+        line.to_lowercase().contains(&self.query)
+      } else {
+        line.contains(&self.query)
+      }
+    })
+  }
+
+  /// Jumps to the next match above the last one (`backward`) or below it,
+  /// continuing from the other end at the top or bottom of the scrollback.
+  /// Without a previous match the search starts at the edge of the visible
+  /// part. Returns `false` if there are no matches.
+  pub fn find(&mut self, vt: &mut vt100::Parser, backward: bool) -> bool {
+    self.find_in(vt, backward, None, false)
+      || self.find_in(vt, backward, None, true)
+  }
+
+  /// Jumps to the next match in `vt`, without going around. `block` is the
+  /// archived block shown in `vt`, see `Hit::block`. With `from_edge`, the
+  /// search starts at the bottom (`backward`) or top of the scrollback,
+  /// otherwise after the last match if it is in `vt`, or at the edge of the
+  /// visible part.
+  pub fn find_in(
+    &mut self,
+    vt: &mut vt100::Parser,
+    backward: bool,
+    block: Option<usize>,
+    from_edge: bool,
+  ) -> bool {
+    let top = -(scrollback_rows(vt) as i32);
+    let screen = vt.screen();
+    let scrollback = screen.scrollback() as i32;
+    let (rows, _) = screen.size();
+    let bottom = rows as i32 - 1;
+
+    let last = self.hit.filter(|hit| hit.block == block);
+    let (mut y, after) = match last {
+      _ if from_edge => (if backward { bottom } else { top }, None),
+      Some(hit) => (hit.y, Some(hit.start)),
+      None if backward => (bottom - scrollback, None),
+      None => (-scrollback, None),
+    };
+
+    let start_y = y;
+    let mut found = None;
+    while top <= y && y <= bottom {
+      // Rows are only reachable by scrolling to them.
+      let offset = (-y).max(0);
+      vt.set_scrollback(offset as usize);
+      let matches = self.row_matches(vt.screen(), (y + offset) as u16);
+      // On the row of the last match, only matches past it count.
+      let past = |start: u16| match after {
+        Some(after) if y == start_y => {
+          if backward {
+            start < after
+          } else {
+            start > after
+          }
+        }
+        _ => true,
+      };
+      let hit = if backward {
+        matches.into_iter().rev().find(|(start, _)| past(*start))
+      } else {
+        matches.into_iter().find(|(start, _)| past(*start))
+      };
+      if let Some((start, end)) = hit {
+        found = Some(Hit {
+          block,
+          y,
+          start,
+          end,
+        });
+        break;
+      }
+      y += if backward { -1 } else { 1 };
+    }
+
+    let hit = match found {
+      Some(hit) => hit,
+      None => {
+        vt.set_scrollback(scrollback as usize);
+        return false;
+      }
+    };
+    // Keep the view if the match is visible, otherwise center it.
+    let visible = -scrollback <= hit.y && hit.y < rows as i32 - scrollback;
+    let offset = if visible {
+      scrollback
+    } else {
+      (rows as i32 / 2 - hit.y).clamp(0, -top)
+    };
+    vt.set_scrollback(offset as usize);
+    self.hit = Some(hit);
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hits(
+    search: &mut Search,
+    vt: &mut vt100::Parser,
+    backward: bool,
+  ) -> (i32, u16, usize) {
+    assert!(search.find(vt, backward));
+    let hit = search.hit.unwrap();
+    (hit.y, hit.start, vt.screen().scrollback())
+  }
+
+  #[test]
+  fn find_matches() {
+    let mut vt = vt100::Parser::new(4, 20, 100);
+    for i in 0..20 {
+      if i % 5 == 2 {
+        vt.process(format!("{} Error, error\r\n", i).as_bytes());
+      } else {
+        vt.process(format!("{} ok\r\n", i).as_bytes());
+      }
+    }
+    // 17 rows of scrollback. Errors are on lines 2, 7, 12 and 17, and line
+    // 17 is at the top of the screen.
+    let mut search = Search::new("error").unwrap();
+    assert_eq!(hits(&mut search, &mut vt, true), (0, 10, 0));
+    assert_eq!(hits(&mut search, &mut vt, true), (0, 3, 0));
+    assert_eq!(hits(&mut search, &mut vt, true), (-5, 10, 7));
+    assert_eq!(hits(&mut search, &mut vt, false), (0, 3, 2));
+    assert_eq!(hits(&mut search, &mut vt, false), (0, 10, 2));
+    // Going around from the bottom to the top.
+    assert_eq!(hits(&mut search, &mut vt, false), (-15, 2, 17));
+
+    let mut search = Search::new("Error").unwrap();
+    assert_eq!(hits(&mut search, &mut vt, false), (-15, 2, 17));
+    assert_eq!(hits(&mut search, &mut vt, false), (-10, 2, 12));
+
+    vt.set_scrollback(0);
+    let mut search = Search::new("8 OK").unwrap();
+    assert!(!search.find(&mut vt, true));
+    let mut search = Search::new("8 ok").unwrap();
+    assert_eq!(hits(&mut search, &mut vt, true), (1, 1, 0));
+    assert_eq!(search.row_matches(vt.screen(), 1), vec![(1, 5)]);
+
+    assert!(Search::new("").is_none());
+    let mut search = Search::new("missing").unwrap();
+    assert!(!search.find(&mut vt, true));
+    assert_eq!(vt.screen().scrollback(), 0);
+  }
+}
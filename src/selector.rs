@@ -0,0 +1,42 @@
+//! Choosing the processes a command acts on.
+
+use serde::{Deserialize, Serialize};
+
+use crate::watch::match_component;
+
+/// `api` selects the process named "api", `web-*` the processes with names
+/// matching the pattern (`*` and `?` are wildcards) and `tag:backend` the
+/// processes with the tag "backend".
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct ProcSelector(pub String);
+
+impl ProcSelector {
+  pub fn matches(&self, name: &str, tags: &[String]) -> bool {
+    match self.0.strip_prefix("tag:") {
+      Some(tag) => tags.iter().any(|t| t == tag),
+      None => match_component(self.0.as_bytes(), name.as_bytes()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn select_procs() {
+    let tags = vec!["backend".to_string(), "db".to_string()];
+    let select = |selector: &str, name: &str| {
+      ProcSelector(selector.to_string()).matches(name, &tags)
+    };
+    assert!(select("api", "api"));
+    assert!(!select("api", "api-v2"));
+    assert!(select("api*", "api-v2"));
+    assert!(select("web-?", "web-1"));
+    assert!(!select("web-?", "web-10"));
+    assert!(select("tag:db", "postgres"));
+    assert!(!select("tag:frontend", "postgres"));
+    assert!(!select("tag:", "postgres"));
+  }
+}
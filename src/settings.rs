@@ -6,6 +6,7 @@ use indexmap::IndexMap;
 use serde_yaml::Value;
 
 use crate::{
+  config::{LogConfig, Scrollback},
   event::{AppEvent, CopyMove},
   key::Key,
   keymap::Keymap,
@@ -20,6 +21,9 @@ pub struct Settings {
   pub hide_keymap_window: bool,
   pub mouse_scroll_speed: usize,
   pub proc_list_width: usize,
+  /// Default `log` of all processes.
+  pub log: Option<LogConfig>,
+  pub scrollback: Scrollback,
 }
 
 impl Default for Settings {
@@ -31,6 +35,8 @@ impl Default for Settings {
       hide_keymap_window: false,
       mouse_scroll_speed: 5,
       proc_list_width: 30,
+      log: None,
+      scrollback: Scrollback::default(),
     };
     settings.add_defaults();
     settings
@@ -88,9 +94,9 @@ impl Settings {
   pub fn merge_value(&mut self, val: Val) -> Result<()> {
     let obj = val.as_object()?;
 
-    fn add_keys<'a>(
+    fn add_keys(
       into: &mut IndexMap<Key, AppEvent>,
-      val: Option<&'a Val>,
+      val: Option<&Val>,
     ) -> Result<()> {
       if let Some(keymap) = val {
         let mut keymap = keymap.as_object()?;
@@ -102,11 +108,14 @@ impl Settings {
         }
 
         for (key, event) in keymap {
-          let key = Key::parse(value_to_string(&key)?.as_str())?;
+          let key = Key::parse(value_to_string(&key)?.as_str())
+            .map_err(|err| event.error_at(err.to_string()))?;
           if event.raw().is_null() {
             into.shift_remove(&key);
           } else {
-            let event: AppEvent = serde_yaml::from_value(event.raw().clone())?;
+            let event: AppEvent =
+              serde_yaml::from_value(event.raw().clone())
+                .map_err(|err| event.error_at(err.to_string()))?;
             into.insert(key, event);
           }
         }
@@ -136,6 +145,14 @@ impl Settings {
       self.proc_list_width = proc_list_width.as_usize()?;
     }
 
+    if let Some(log) = obj.get(&Value::from("log")) {
+      self.log = LogConfig::from_val(log, self.log.as_ref())?;
+    }
+
+    if let Some(scrollback) = obj.get(&Value::from("scrollback")) {
+      self.scrollback = Scrollback::from_val(scrollback)?;
+    }
+
     Ok(())
   }
 
@@ -175,26 +192,32 @@ impl Settings {
     );
     s.keymap_add_p(
       Key::new(KeyCode::Char('s'), KeyModifiers::NONE),
-      AppEvent::StartProc,
+      AppEvent::StartProc { proc: None },
     );
     s.keymap_add_p(
       Key::new(KeyCode::Char('x'), KeyModifiers::NONE),
-      AppEvent::TermProc,
+      AppEvent::TermProc { proc: None },
     );
     s.keymap_add_p(
       Key::new(KeyCode::Char('X'), KeyModifiers::SHIFT),
-      AppEvent::KillProc,
+      AppEvent::KillProc { proc: None },
     );
     s.keymap_add_p(
       Key::new(KeyCode::Char('r'), KeyModifiers::NONE),
-      AppEvent::RestartProc,
+      AppEvent::RestartProc { proc: None },
     );
     s.keymap_add_p(
       Key::new(KeyCode::Char('R'), KeyModifiers::SHIFT),
-      AppEvent::ForceRestartProc,
+      AppEvent::ForceRestartProc { proc: None },
     );
     let ctrlc = Key::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
-    s.keymap_add_p(ctrlc, AppEvent::SendKey { key: ctrlc });
+    s.keymap_add_p(
+      ctrlc,
+      AppEvent::SendKey {
+        key: ctrlc,
+        proc: None,
+      },
+    );
     s.keymap_add_p(
       Key::new(KeyCode::Char('a'), KeyModifiers::NONE),
       AppEvent::ShowAddProc,
@@ -228,6 +251,19 @@ impl Settings {
       );
     }
 
+    s.keymap_add_p(
+      Key::new(KeyCode::Char('/'), KeyModifiers::NONE),
+      AppEvent::SearchStart,
+    );
+    s.keymap_add_p(
+      Key::new(KeyCode::Char('n'), KeyModifiers::NONE),
+      AppEvent::SearchNext,
+    );
+    s.keymap_add_p(
+      Key::new(KeyCode::Char('N'), KeyModifiers::SHIFT),
+      AppEvent::SearchPrev,
+    );
+
     s.keymap_add_p(
       Key::new(KeyCode::Char('z'), KeyModifiers::NONE),
       AppEvent::Zoom,
@@ -0,0 +1,70 @@
+//! Signals for process trees. Processes are spawned by portable-pty as
+//! session leaders, so the process group and the session id of a process
+//! are equal to its pid.
+//!
+//! A pid can be reused once its process is reaped. Callers only pass pids
+//! of processes that are not reaped yet, see `reap`.
+
+/// Sends a signal to the process group of `pid`.
+pub fn signal_group(pid: u32, sig: libc::c_int) {
+  if pid == 0 {
+    return;
+  }
+  unsafe { libc::kill(-(pid as libc::pid_t), sig) };
+}
+
+/// Kills the process group of `pid` and any remaining processes of its
+/// session, including descendants that moved to their own process group.
+pub fn kill_session(pid: u32) {
+  if pid == 0 {
+    return;
+  }
+  signal_group(pid, libc::SIGKILL);
+  for member in session_members(pid as libc::pid_t) {
+    unsafe { libc::kill(member, libc::SIGKILL) };
+  }
+}
+
+/// Releases the pid of an exited process. Until then it stays a zombie, which
+/// keeps its pid from being reused as a process, group or session id.
+pub fn reap(pid: u32) {
+  if pid == 0 {
+    return;
+  }
+  loop {
+    let ret =
+      unsafe { libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), 0) };
+    if ret != -1
+      || std::io::Error::last_os_error().kind()
+        != std::io::ErrorKind::Interrupted
+    {
+      break;
+    }
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn session_members(sid: libc::pid_t) -> Vec<libc::pid_t> {
+  let dir = match std::fs::read_dir("/proc") {
+    Ok(dir) => dir,
+    Err(_) => return Vec::new(),
+  };
+  dir
+    .filter_map(|entry| {
+      let pid: libc::pid_t = entry.ok()?.file_name().to_str()?.parse().ok()?;
+      let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+      // The command name is in parens and may contain spaces. Fields after
+      // it: state, ppid, pgrp, session.
+      let rest = stat.get(stat.rfind(')')? + 1..)?;
+      let session: libc::pid_t =
+        rest.split_whitespace().nth(3)?.parse().ok()?;
+      (session == sid).then_some(pid)
+    })
+    .collect()
+}
+
+/// Without procfs only the process group is killed.
+#[cfg(not(target_os = "linux"))]
+fn session_members(_sid: libc::pid_t) -> Vec<libc::pid_t> {
+  Vec::new()
+}
@@ -1,8 +1,11 @@
 use tui_input::Input;
 
 use crate::{
+  config::{ExitAction, OnExitConfig},
+  event::AppEvent,
   keymap::KeymapGroup,
-  proc::{CopyMode, Proc},
+  proc::{CopyMode, Proc, ProcState},
+  selector::ProcSelector,
 };
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -38,6 +41,8 @@ pub struct State {
   pub modal: Option<Modal>,
 
   pub quitting: bool,
+  /// Exit code of mprocs, set when an `on_exit` policy quits.
+  pub exit_code: Option<i32>,
 }
 
 impl State {
@@ -60,6 +65,65 @@ impl State {
     self.procs.iter_mut().find(|proc| proc.id == id)
   }
 
+  /// The processes matching `selector`, or the selected one without it.
+  pub fn target_procs_mut(
+    &mut self,
+    selector: Option<&ProcSelector>,
+  ) -> Vec<&mut Proc> {
+    match selector {
+      Some(selector) => self
+        .procs
+        .iter_mut()
+        .filter(|proc| selector.matches(&proc.name, &proc.tags))
+        .collect(),
+      None => self.get_current_proc_mut().into_iter().collect(),
+    }
+  }
+
+  /// Applies commands that act on processes, in the UI and in headless mode.
+  /// Returns `false` for other commands.
+  pub fn handle_proc_event(&mut self, event: &AppEvent) -> bool {
+    match event {
+      AppEvent::StartProc { proc } => {
+        for proc in self.target_procs_mut(proc.as_ref()) {
+          proc.start();
+        }
+      }
+      AppEvent::TermProc { proc } => {
+        for proc in self.target_procs_mut(proc.as_ref()) {
+          proc.stop();
+        }
+      }
+      AppEvent::KillProc { proc } => {
+        for proc in self.target_procs_mut(proc.as_ref()) {
+          proc.kill();
+        }
+      }
+      AppEvent::RestartProc { proc } => {
+        for proc in self.target_procs_mut(proc.as_ref()) {
+          proc.restart();
+        }
+      }
+      AppEvent::ForceRestartProc { proc } => {
+        for proc in self.target_procs_mut(proc.as_ref()) {
+          if proc.is_up() {
+            proc.kill();
+            proc.to_restart = true;
+          } else {
+            proc.start();
+          }
+        }
+      }
+      AppEvent::SendKey { key, proc } => {
+        for proc in self.target_procs_mut(proc.as_ref()) {
+          proc.send_key(key);
+        }
+      }
+      _ => return false,
+    }
+    true
+  }
+
   pub fn get_keymap_group(&self) -> KeymapGroup {
     match self.scope {
       Scope::Procs => KeymapGroup::Procs,
@@ -73,13 +137,113 @@ impl State {
     }
   }
 
+  pub fn is_proc_ready(&self, name: &str) -> bool {
+    self
+      .procs
+      .iter()
+      .any(|proc| proc.name == name && proc.is_ready())
+  }
+
   pub fn all_procs_down(&self) -> bool {
     self.procs.iter().all(|proc| !proc.is_up())
   }
+
+  /// Applies `on_exit` policies after the process at `index` has exited.
+  /// Exits requested by mprocs or the user and exits followed by a restart
+  /// only count for `all_done`.
+  pub fn apply_exit_policy(&mut self, on_exit: &OnExitConfig, index: usize) {
+    if self.quitting {
+      return;
+    }
+
+    let proc = &self.procs[index];
+    let action = match proc.exit_status {
+      Some(status)
+        if !proc.stop_requested()
+          && !proc.restart_pending()
+          && !proc.to_restart =>
+      {
+        match on_exit.procs.get(&proc.name) {
+          Some(action) => Some((*action, status)),
+          None if !status.success() => {
+            on_exit.any_fails.map(|action| (action, status))
+          }
+          None => None,
+        }
+      }
+      _ => None,
+    };
+
+    match action {
+      Some((ExitAction::StopAll, _)) => self.stop_all(),
+      Some((ExitAction::Quit, _)) => self.quit(self.combined_exit_code()),
+      Some((ExitAction::QuitWithCode, status)) => self.quit(status.exit_code()),
+      None => {
+        let all_done = self.procs.iter().all(|proc| {
+          !proc.is_up() && !proc.restart_pending() && !proc.to_restart
+        });
+        if on_exit.all_done && all_done {
+          // Processes still waiting for dependencies will never run, like
+          // when a dependency exited before it was ready.
+          let not_started = self.procs.iter().any(|proc| proc.waiting_for_deps);
+          let exit_code = if not_started {
+            1
+          } else {
+            self.combined_exit_code()
+          };
+          self.quit(exit_code);
+        }
+      }
+    }
+  }
+
+  fn stop_all(&mut self) {
+    for proc in &mut self.procs {
+      proc.stop_or_cancel();
+    }
+  }
+
+  fn quit(&mut self, exit_code: i32) {
+    self.stop_all();
+    self.quitting = true;
+    self.exit_code = Some(exit_code);
+  }
+
+  /// 1 if a process failed to start or failed on its own, 0 otherwise.
+  pub fn combined_exit_code(&self) -> i32 {
+    let failed = self.procs.iter().any(|proc| {
+      matches!(proc.inst, ProcState::Error(_))
+        || matches!(proc.exit_status,
+          Some(status) if !status.success() && !proc.stop_requested())
+    });
+    if failed {
+      1
+    } else {
+      0
+    }
+  }
+
+  /// Starts processes postponed by `depends_on` once all their dependencies
+  /// are ready.
+  pub fn start_waiting_procs(&mut self) {
+    if self.quitting {
+      return;
+    }
+
+    while let Some(index) = self.procs.iter().position(|proc| {
+      proc.waiting_for_deps
+        && proc.depends_on.iter().all(|dep| self.is_proc_ready(dep))
+    }) {
+      let proc = &mut self.procs[index];
+      proc.waiting_for_deps = false;
+      proc.start();
+    }
+  }
 }
 
 pub enum Modal {
   AddProc { input: Input },
+  Search { input: Input },
   RemoveProc { id: usize },
   Quit,
 }
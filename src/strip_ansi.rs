@@ -0,0 +1,92 @@
+/// Removes terminal escape sequences and control characters (except newlines
+/// and tabs) from a byte stream. Keeps state between chunks, so sequences
+/// split across reads are handled.
+#[derive(Default)]
+pub struct AnsiStripper {
+  state: State,
+}
+
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+enum State {
+  #[default]
+  Ground,
+  Esc,
+  EscIntermediate,
+  Csi,
+  /// OSC, DCS, SOS, PM and APC strings. Terminated by BEL or ST.
+  Str,
+  StrEsc,
+}
+
+impl AnsiStripper {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn strip_into(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+      self.state = match self.state {
+        State::Ground => match b {
+          0x1b => State::Esc,
+          b'\n' | b'\t' => {
+            out.push(b);
+            State::Ground
+          }
+          0x00..=0x1f | 0x7f => State::Ground,
+          _ => {
+            out.push(b);
+            State::Ground
+          }
+        },
+        State::Esc => match b {
+          b'[' => State::Csi,
+          b']' | b'P' | b'X' | b'^' | b'_' => State::Str,
+          0x20..=0x2f => State::EscIntermediate,
+          _ => State::Ground,
+        },
+        State::EscIntermediate => match b {
+          0x20..=0x2f => State::EscIntermediate,
+          _ => State::Ground,
+        },
+        State::Csi => match b {
+          0x40..=0x7e => State::Ground,
+          _ => State::Csi,
+        },
+        State::Str => match b {
+          0x07 => State::Ground,
+          0x1b => State::StrEsc,
+          _ => State::Str,
+        },
+        State::StrEsc => match b {
+          b'\\' => State::Ground,
+          _ => State::Str,
+        },
+      };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strip(chunks: &[&[u8]]) -> String {
+    let mut stripper = AnsiStripper::new();
+    let mut out = Vec::new();
+    for chunk in chunks {
+      stripper.strip_into(chunk, &mut out);
+    }
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn strips_sequences() {
+    assert_eq!(
+      strip(&[b"\x1b[32mListening\x1b[0m on\r\n"]),
+      "Listening on\n"
+    );
+    assert_eq!(strip(&[b"\x1b]0;title\x07a\x1b]0;t\x1b\\b"]), "ab");
+    assert_eq!(strip(&[b"\x1b(Bx\x1b=y"]), "xy");
+    assert_eq!(strip(&[b"a\x1b[", b"1;3", b"1mb"]), "ab");
+  }
+}
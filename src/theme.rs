@@ -16,7 +16,7 @@ impl Theme {
     }
   }
 
-  pub fn pane(&self, active: bool) -> Block {
+  pub fn pane(&self, active: bool) -> Block<'_> {
     let style = self.style(active);
 
     Block::default().borders(Borders::ALL).border_style(style)
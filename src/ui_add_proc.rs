@@ -19,14 +19,24 @@ pub fn render_add_proc(
   input: &mut Input,
 ) {
   let theme = Theme::default();
+  let title = Span::styled("Add process", theme.style(true));
+  render_input(area, frame, input, title);
+}
+
+/// Single line text input in the middle of the screen.
+pub fn render_input(
+  area: Rect,
+  frame: &mut Frame<Backend>,
+  input: &mut Input,
+  title: Span,
+) {
+  let theme = Theme::default();
 
   let y = area.height / 2;
   let x = (area.width / 2).saturating_sub(20).max(1);
   let w = 39.min(area.width.saturating_sub(3));
 
-  let block = theme
-    .pane(true)
-    .title(Span::styled("Add process", theme.style(true)));
+  let block = theme.pane(true).title(title);
   frame.render_widget(block, Rect::new(x - 1, y - 1, 42, 3).intersection(area));
 
   let left_trim = input.cursor().saturating_sub(w as usize);
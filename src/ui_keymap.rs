@@ -40,9 +40,9 @@ pub fn render_keymap(
       AppEvent::QuitOrAsk,
       AppEvent::NextProc,
       AppEvent::PrevProc,
-      AppEvent::StartProc,
-      AppEvent::TermProc,
-      AppEvent::RestartProc,
+      AppEvent::StartProc { proc: None },
+      AppEvent::TermProc { proc: None },
+      AppEvent::RestartProc { proc: None },
     ],
     KeymapGroup::Term => vec![AppEvent::ToggleFocus],
     KeymapGroup::Copy => vec![
@@ -10,7 +10,7 @@ use tui::{
 };
 
 use crate::{
-  proc::Proc,
+  proc::{ExitStatus, Proc},
   state::{Scope, State},
   theme::Theme,
 };
@@ -77,15 +77,56 @@ fn create_proc_item<'a>(
   width: u16,
   theme: &Theme,
 ) -> ListItem<'a> {
-  let status = if proc.is_up() {
+  let status = if let Some(left) = proc.stop_time_left() {
+    let secs = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+    Span::styled(
+      format!(" STOP {}s ", secs),
+      Style::default()
+        .fg(Color::LightRed)
+        .add_modifier(Modifier::BOLD),
+    )
+  } else if proc.is_up() && proc.ready {
+    Span::styled(
+      " READY ",
+      Style::default()
+        .fg(Color::LightGreen)
+        .add_modifier(Modifier::BOLD),
+    )
+  } else if proc.is_up() && proc.has_ready_probe() {
+    Span::styled(" UP ", Style::default().fg(Color::LightYellow))
+  } else if proc.is_up() {
     Span::styled(
       " UP ",
       Style::default()
         .fg(Color::LightGreen)
         .add_modifier(Modifier::BOLD),
     )
+  } else if proc.waiting_for_deps {
+    Span::styled(" WAIT ", Style::default().fg(Color::Yellow))
   } else {
-    Span::styled(" DOWN ", Style::default().fg(Color::LightRed))
+    match proc.exit_status {
+      Some(ExitStatus {
+        code: Some(0),
+        signal: _,
+      }) => Span::styled(" EXIT 0 ", Style::default().fg(Color::Gray)),
+      Some(ExitStatus {
+        code: Some(code),
+        signal: _,
+      }) => Span::styled(
+        format!(" EXIT {} ", code),
+        Style::default()
+          .fg(Color::LightRed)
+          .add_modifier(Modifier::BOLD),
+      ),
+      Some(ExitStatus {
+        code: None,
+        signal: Some(signal),
+      }) => Span::styled(
+        format!(" KILLED({}) ", signal),
+        Style::default().fg(Color::LightMagenta),
+      ),
+      _ => Span::styled(" DOWN ", Style::default().fg(Color::LightRed)),
+    }
   };
 
   let mark = if is_cur {
@@ -136,7 +177,7 @@ pub fn procs_get_clicked_index(
     let scroll = (state.selected + 1).saturating_sub(inner.height as usize);
     let index = index as usize + scroll;
     if index < state.procs.len() {
-      return Some(index as usize);
+      return Some(index);
     }
   }
   None
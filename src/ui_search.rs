@@ -0,0 +1,18 @@
+use std::io;
+
+use tui::{backend::CrosstermBackend, layout::Rect, text::Span, Frame};
+use tui_input::Input;
+
+use crate::{theme::Theme, ui_add_proc::render_input};
+
+type Backend = CrosstermBackend<io::Stdout>;
+
+pub fn render_search(
+  area: Rect,
+  frame: &mut Frame<Backend>,
+  input: &mut Input,
+) {
+  let theme = Theme::default();
+  let title = Span::styled("Search", theme.style(true));
+  render_input(area, frame, input, title);
+}
@@ -11,6 +11,7 @@ use tui::{
 
 use crate::{
   proc::{CopyMode, Pos, ProcState},
+  search::Search,
   state::{Scope, State},
   theme::Theme,
 };
@@ -30,7 +31,7 @@ pub fn render_term(area: Rect, frame: &mut Frame<Backend>, state: &mut State) {
   };
 
   if let Some(proc) = state.get_current_proc() {
-    let mut title = Vec::with_capacity(4);
+    let mut title = Vec::with_capacity(6);
     title.push(Span::styled("Terminal", theme.style(active)));
     match proc.copy_mode {
       CopyMode::None(_) => (),
@@ -39,6 +40,12 @@ pub fn render_term(area: Rect, frame: &mut Frame<Backend>, state: &mut State) {
         title.push(Span::styled("COPY MODE", theme.copy_mode_label()));
       }
     };
+    if let ProcState::Some(inst) = &proc.inst {
+      if inst.in_history() {
+        title.push(Span::raw(" "));
+        title.push(Span::styled("HISTORY", theme.copy_mode_label()));
+      }
+    }
 
     let block = theme
       .pane(active)
@@ -50,7 +57,7 @@ pub fn render_term(area: Rect, frame: &mut Frame<Backend>, state: &mut State) {
     match &proc.inst {
       ProcState::None => (),
       ProcState::Some(inst) => {
-        let vt = inst.vt.read();
+        let vt = inst.vt().read();
         match vt {
           Ok(vt) => {
             let (screen, cursor) = match &proc.copy_mode {
@@ -77,7 +84,12 @@ pub fn render_term(area: Rect, frame: &mut Frame<Backend>, state: &mut State) {
               }
             };
 
-            let term = UiTerm::new(screen, &proc.copy_mode);
+            let term = UiTerm::new(
+              screen,
+              &proc.copy_mode,
+              proc.search.as_ref(),
+              inst.history_block(),
+            );
             frame.render_widget(
               term,
               area.inner(&Margin {
@@ -122,11 +134,24 @@ pub fn render_term(area: Rect, frame: &mut Frame<Backend>, state: &mut State) {
 pub struct UiTerm<'a> {
   screen: &'a vt100::Screen,
   copy_mode: &'a CopyMode,
+  search: Option<&'a Search>,
+  /// Block of the archived output in `screen`, see `Hit::block`.
+  block: Option<usize>,
 }
 
 impl<'a> UiTerm<'a> {
-  pub fn new(screen: &'a vt100::Screen, copy_mode: &'a CopyMode) -> Self {
-    UiTerm { screen, copy_mode }
+  pub fn new(
+    screen: &'a vt100::Screen,
+    copy_mode: &'a CopyMode,
+    search: Option<&'a Search>,
+    block: Option<usize>,
+  ) -> Self {
+    UiTerm {
+      screen,
+      copy_mode,
+      search,
+      block,
+    }
   }
 }
 
@@ -135,7 +160,20 @@ impl Widget for UiTerm<'_> {
     let screen = self.screen;
 
     for row in 0..area.height {
+      let y = row as i32 - screen.scrollback() as i32;
+      let matches = self
+        .search
+        .map_or_else(Vec::new, |search| search.row_matches(screen, row));
+      let hit = self
+        .search
+        .and_then(|search| search.hit)
+        .filter(|hit| hit.block == self.block && hit.y == y);
       for col in 0..area.width {
+        let matched = matches
+          .iter()
+          .any(|(start, end)| *start <= col && col < *end);
+        let current =
+          matches!(hit, Some(hit) if hit.start <= col && col < hit.end);
         let to_cell = buf.get_mut(area.x + col, area.y + row);
         if let Some(cell) = screen.cell(row, col) {
           if cell.has_contents() {
@@ -163,6 +201,8 @@ impl Widget for UiTerm<'_> {
               {
                 (Some(Color::Black), Some(Color::Cyan))
               }
+              _ if current => (Some(Color::Black), Some(Color::LightRed)),
+              _ if matched => (Some(Color::Black), Some(Color::Yellow)),
               _ => (conv_color(cell.fgcolor()), conv_color(cell.bgcolor())),
             };
 
@@ -178,6 +218,11 @@ impl Widget for UiTerm<'_> {
           } else {
             // Cell doesn't have content.
             to_cell.set_char(' ');
+            if current {
+              to_cell.set_bg(Color::LightRed);
+            } else if matched {
+              to_cell.set_bg(Color::Yellow);
+            }
           }
         } else {
           // Out of bounds.
@@ -211,7 +256,7 @@ fn conv_color(color: vt100::Color) -> Option<tui::style::Color> {
 
 pub fn term_check_hit(area: Rect, x: u16, y: u16) -> bool {
   area.x <= x
-    && area.x + area.width >= x + 1
+    && area.x + area.width > x
     && area.y <= y
-    && area.y + area.height >= y + 1
+    && area.y + area.height > y
 }
@@ -30,7 +30,7 @@ pub fn render_zoom_tip(
     .find_map(|event| keymap.resolve_key(KeymapGroup::Term, &event));
 
   let line = if let Some(key) = key {
-    Text::from(format!(" To exit zoom mode press {}", key.to_string()))
+    Text::from(format!(" To exit zoom mode press {}", key))
   } else {
     Text::from(" No key bound to exit the zoom mode")
   };
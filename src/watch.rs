@@ -0,0 +1,469 @@
+//! Restarts processes when watched files change. The directories that
+//! watched paths can match, except ignored ones, are watched with the native
+//! notification API of the platform and listed again only after it reports
+//! a change. If the notifications can't be used, for example when the limit
+//! of inotify watches is reached, the directories are listed every second.
+
+use std::{
+  collections::{HashMap, HashSet},
+  ffi::OsString,
+  path::{Component, Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::RecvTimeoutError,
+    Arc,
+  },
+  time::{Duration, Instant, SystemTime},
+};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{config::WatchConfig, proc::ProcUpdate};
+
+/// How often files are listed without notifications. With notifications
+/// this is how often the thread checks that the watcher is still alive.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches files of a single process in a background thread. The thread ends
+/// when the watcher is dropped.
+pub struct FileWatcher {
+  alive: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+  pub fn spawn(
+    id: usize,
+    cfg: &WatchConfig,
+    cwd: Option<&OsString>,
+    tx: UnboundedSender<(usize, ProcUpdate)>,
+  ) -> Self {
+    let alive = Arc::new(AtomicBool::new(true));
+
+    let cwd = match cwd {
+      Some(cwd) => PathBuf::from(cwd),
+      None => std::env::current_dir().unwrap_or_default(),
+    };
+    let scanner = Scanner {
+      paths: cfg.paths.iter().map(|p| Glob::new(p)).collect(),
+      ignore: cfg.ignore.iter().map(|p| Glob::new(p)).collect(),
+      cwd,
+    };
+    let thread = WatchThread {
+      id,
+      scanner,
+      debounce: cfg.debounce,
+      tx,
+      alive: alive.clone(),
+    };
+    std::thread::spawn(move || thread.run());
+
+    FileWatcher { alive }
+  }
+}
+
+impl Drop for FileWatcher {
+  fn drop(&mut self) {
+    self.alive.store(false, Ordering::Relaxed);
+  }
+}
+
+struct WatchThread {
+  id: usize,
+  scanner: Scanner,
+  debounce: Duration,
+  tx: UnboundedSender<(usize, ProcUpdate)>,
+  alive: Arc<AtomicBool>,
+}
+
+impl WatchThread {
+  fn run(self) {
+    let mut snapshot = self.scanner.scan();
+    if let Err(err) = self.wait_for_events(&mut snapshot) {
+      log::warn!("Watching files failed, polling instead: {}", err);
+      self.poll(snapshot.files);
+    }
+  }
+
+  /// Lists the watched directories again when the notifier reports a change
+  /// that isn't ignored and no more changes follow for `debounce`.
+  fn wait_for_events(&self, snapshot: &mut Snapshot) -> notify::Result<()> {
+    let (event_tx, event_rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(event_tx)?;
+    let mut watched = HashSet::new();
+    update_watches(&mut watcher, &mut watched, &snapshot.dirs)?;
+
+    let mut changed_at: Option<Instant> = None;
+    while self.alive.load(Ordering::Relaxed) {
+      let timeout = match changed_at {
+        Some(at) => self.debounce.saturating_sub(at.elapsed()),
+        None => POLL_INTERVAL,
+      };
+      match event_rx.recv_timeout(timeout.min(POLL_INTERVAL)) {
+        Ok(event) => {
+          let event = event?;
+          if event
+            .paths
+            .iter()
+            .any(|path| !self.scanner.is_ignored(path))
+          {
+            changed_at = Some(Instant::now());
+          }
+        }
+        Err(RecvTimeoutError::Timeout) => (),
+        Err(RecvTimeoutError::Disconnected) => break,
+      }
+
+      if let Some(at) = changed_at {
+        if at.elapsed() >= self.debounce {
+          changed_at = None;
+          let cur = self.scanner.scan();
+          update_watches(&mut watcher, &mut watched, &cur.dirs)?;
+          let changed = cur.files != snapshot.files;
+          *snapshot = cur;
+          if changed && !self.send_changed() {
+            break;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Lists the watched directories every `POLL_INTERVAL`.
+  fn poll(&self, mut files: HashMap<PathBuf, SystemTime>) {
+    let mut changed_at: Option<Instant> = None;
+    while self.alive.load(Ordering::Relaxed) {
+      std::thread::sleep(POLL_INTERVAL);
+
+      let cur_files = self.scanner.scan().files;
+      if cur_files != files {
+        files = cur_files;
+        changed_at = Some(Instant::now());
+      } else if let Some(at) = changed_at {
+        if at.elapsed() >= self.debounce {
+          changed_at = None;
+          if !self.send_changed() {
+            break;
+          }
+        }
+      }
+    }
+  }
+
+  /// Returns `false` if the process is gone.
+  fn send_changed(&self) -> bool {
+    self.alive.load(Ordering::Relaxed)
+      && self.tx.send((self.id, ProcUpdate::FilesChanged)).is_ok()
+  }
+}
+
+/// Watches new directories and stops watching the ones that are no longer
+/// listed. Directories that are removed in the meantime are skipped.
+fn update_watches(
+  watcher: &mut impl Watcher,
+  watched: &mut HashSet<PathBuf>,
+  dirs: &HashSet<PathBuf>,
+) -> notify::Result<()> {
+  for dir in watched.iter().filter(|dir| !dirs.contains(*dir)) {
+    let _ = watcher.unwatch(dir);
+  }
+  watched.retain(|dir| dirs.contains(dir));
+  for dir in dirs {
+    if watched.contains(dir) {
+      continue;
+    }
+    match watcher.watch(dir, RecursiveMode::NonRecursive) {
+      Ok(()) => {
+        watched.insert(dir.clone());
+      }
+      Err(err) if is_not_found(&err) => (),
+      Err(err) => return Err(err),
+    }
+  }
+  Ok(())
+}
+
+fn is_not_found(err: &notify::Error) -> bool {
+  match &err.kind {
+    notify::ErrorKind::PathNotFound => true,
+    notify::ErrorKind::Io(err) => err.kind() == std::io::ErrorKind::NotFound,
+    _ => false,
+  }
+}
+
+/// Modification times of matching files and the directories listed to find
+/// them.
+#[derive(Default)]
+struct Snapshot {
+  files: HashMap<PathBuf, SystemTime>,
+  dirs: HashSet<PathBuf>,
+}
+
+struct Scanner {
+  paths: Vec<Glob>,
+  ignore: Vec<Glob>,
+  cwd: PathBuf,
+}
+
+impl Scanner {
+  /// Collects modification times of all matching files.
+  fn scan(&self) -> Snapshot {
+    let mut snapshot = Snapshot::default();
+    for glob in &self.paths {
+      let (base, mut segments) = glob.split_base();
+      let base = self.cwd.join(base);
+      if base.is_dir() {
+        // A plain directory path watches everything inside it.
+        if segments.is_empty() {
+          segments = &[Segment::AnyDirs];
+        }
+      } else if let Some(parent) = base.parent() {
+        // Notices when the file or directory is created.
+        if parent.is_dir() && !self.is_ignored(parent) {
+          snapshot.dirs.insert(parent.to_path_buf());
+        }
+      }
+      let mut rel = Vec::new();
+      self.walk(&base, &mut rel, segments, &mut snapshot);
+    }
+    snapshot
+  }
+
+  /// Ignored directories are skipped without listing them.
+  fn walk(
+    &self,
+    path: &Path,
+    rel: &mut Vec<String>,
+    segments: &[Segment],
+    snapshot: &mut Snapshot,
+  ) {
+    if self.is_ignored(path) {
+      return;
+    }
+    let meta = match std::fs::symlink_metadata(path) {
+      Ok(meta) => meta,
+      Err(_) => return,
+    };
+
+    // Symlinked directories are not followed to avoid cycles.
+    if meta.is_dir() {
+      if !match_prefix(segments, rel) {
+        return;
+      }
+      let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+      };
+      snapshot.dirs.insert(path.to_path_buf());
+      for entry in entries.flatten() {
+        rel.push(entry.file_name().to_string_lossy().to_string());
+        self.walk(&entry.path(), rel, segments, snapshot);
+        rel.pop();
+      }
+    } else if match_segments(segments, rel) {
+      let modified = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+      snapshot.files.insert(path.to_path_buf(), modified);
+    }
+  }
+
+  fn is_ignored(&self, path: &Path) -> bool {
+    if self.ignore.is_empty() {
+      return false;
+    }
+    let path = path.strip_prefix(&self.cwd).unwrap_or(path);
+    let components = path
+      .components()
+      .filter_map(|c| match c {
+        Component::Normal(c) => Some(c.to_string_lossy().to_string()),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    self
+      .ignore
+      .iter()
+      .any(|glob| match_segments(&glob.segments, &components))
+  }
+}
+
+/// Path pattern with `*` and `?` inside path components and `**` matching
+/// any number of directories.
+struct Glob {
+  /// Set for absolute patterns.
+  root: Option<PathBuf>,
+  segments: Vec<Segment>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+  AnyDirs,
+  Pattern(String),
+}
+
+impl Segment {
+  fn is_literal(&self) -> bool {
+    match self {
+      Segment::AnyDirs => false,
+      Segment::Pattern(p) => !p.contains(['*', '?']),
+    }
+  }
+}
+
+impl Glob {
+  fn new(pattern: &str) -> Self {
+    let path = Path::new(pattern);
+    let root = if path.has_root() {
+      Some(
+        path
+          .components()
+          .take_while(|c| {
+            matches!(c, Component::Prefix(_) | Component::RootDir)
+          })
+          .collect(),
+      )
+    } else {
+      None
+    };
+    let segments = path
+      .components()
+      .filter_map(|c| match c {
+        Component::Normal(c) => Some(match c.to_string_lossy().as_ref() {
+          "**" => Segment::AnyDirs,
+          c => Segment::Pattern(c.to_string()),
+        }),
+        Component::ParentDir => Some(Segment::Pattern("..".to_string())),
+        _ => None,
+      })
+      .collect();
+    Glob { root, segments }
+  }
+
+  /// Splits off leading components without wildcards, so only the
+  /// directory they name needs to be scanned.
+  fn split_base(&self) -> (PathBuf, &[Segment]) {
+    let literal = self.segments.iter().take_while(|s| s.is_literal()).count();
+    let mut base = self.root.clone().unwrap_or_default();
+    for segment in &self.segments[..literal] {
+      if let Segment::Pattern(p) = segment {
+        base.push(p);
+      }
+    }
+    (base, &self.segments[literal..])
+  }
+}
+
+fn match_segments(segments: &[Segment], path: &[String]) -> bool {
+  match segments.split_first() {
+    None => path.is_empty(),
+    Some((Segment::AnyDirs, rest)) => {
+      (0..=path.len()).any(|i| match_segments(rest, &path[i..]))
+    }
+    Some((Segment::Pattern(pattern), rest)) => match path.split_first() {
+      Some((name, path)) => {
+        match_component(pattern.as_bytes(), name.as_bytes())
+          && match_segments(rest, path)
+      }
+      None => false,
+    },
+  }
+}
+
+/// Checks whether files inside the directory `path` can match.
+fn match_prefix(segments: &[Segment], path: &[String]) -> bool {
+  match (segments.split_first(), path.split_first()) {
+    (_, None) => true,
+    (None, Some(_)) => false,
+    (Some((Segment::AnyDirs, _)), Some(_)) => true,
+    (Some((Segment::Pattern(pattern), rest)), Some((name, path))) => {
+      match_component(pattern.as_bytes(), name.as_bytes())
+        && match_prefix(rest, path)
+    }
+  }
+}
+
+/// Matches a name against a pattern with `*` and `?` wildcards.
+pub fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+  match pattern.split_first() {
+    None => name.is_empty(),
+    Some((b'*', rest)) => {
+      (0..=name.len()).any(|i| match_component(rest, &name[i..]))
+    }
+    Some((b'?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+    Some((c, rest)) => {
+      name.first() == Some(c) && match_component(rest, &name[1..])
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn matches(pattern: &str, path: &str) -> bool {
+    let path = path.split('/').map(|s| s.to_string()).collect::<Vec<_>>();
+    match_segments(&Glob::new(pattern).segments, &path)
+  }
+
+  #[test]
+  fn glob_matching() {
+    assert!(matches("src/**/*.rs", "src/main.rs"));
+    assert!(matches("src/**/*.rs", "src/a/b/lib.rs"));
+    assert!(!matches("src/**/*.rs", "tests/main.rs"));
+    assert!(!matches("src/*.rs", "src/a/lib.rs"));
+    assert!(matches("*.go", "main.go"));
+    assert!(matches("file?.txt", "file1.txt"));
+    assert!(!matches("file?.txt", "file10.txt"));
+    assert!(matches("target/**", "target"));
+    assert!(matches("**/node_modules/**", "a/node_modules/b/c.js"));
+  }
+
+  #[test]
+  fn ignored_dirs() {
+    let scanner = Scanner {
+      paths: vec![Glob::new("**/*.js")],
+      ignore: vec![Glob::new("**/node_modules/**"), Glob::new("dist/**")],
+      cwd: PathBuf::from("/app"),
+    };
+    // Matching directories are pruned before they are listed.
+    assert!(scanner.is_ignored(Path::new("/app/web/node_modules")));
+    assert!(scanner.is_ignored(Path::new("/app/dist")));
+    assert!(!scanner.is_ignored(Path::new("/app/src")));
+  }
+
+  #[test]
+  fn watched_dirs() {
+    let cwd = std::env::temp_dir()
+      .join(format!("mprocs-test-{}.watch", std::process::id()));
+    std::fs::create_dir_all(cwd.join("src/app")).unwrap();
+    std::fs::create_dir_all(cwd.join("src/node_modules/lib")).unwrap();
+    std::fs::write(cwd.join("src/app/main.js"), "").unwrap();
+    std::fs::write(cwd.join("src/node_modules/lib/index.js"), "").unwrap();
+
+    let scanner = Scanner {
+      paths: vec![Glob::new("src/**/*.js"), Glob::new("package.json")],
+      ignore: vec![Glob::new("**/node_modules/**")],
+      cwd: cwd.clone(),
+    };
+    let snapshot = scanner.scan();
+    let files = snapshot.files.into_keys().collect::<Vec<_>>();
+    assert_eq!(files, vec![cwd.join("src/app/main.js")]);
+    // The parent of a missing file is watched to notice when it's created.
+    let dirs = [&cwd, &cwd.join("src"), &cwd.join("src/app")];
+    assert_eq!(snapshot.dirs, dirs.into_iter().cloned().collect());
+
+    std::fs::remove_dir_all(&cwd).unwrap();
+  }
+
+  #[test]
+  fn glob_base() {
+    let glob = Glob::new("./src/**/*.rs");
+    let (base, rest) = glob.split_base();
+    assert_eq!(base, PathBuf::from("src"));
+    assert_eq!(rest.first(), Some(&Segment::AnyDirs));
+
+    let glob = Glob::new("Cargo.toml");
+    assert_eq!(glob.split_base(), (PathBuf::from("Cargo.toml"), &[][..]));
+  }
+}
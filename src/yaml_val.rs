@@ -1,5 +1,5 @@
 use core::fmt;
-use std::{env::consts::OS, rc::Rc};
+use std::{env::consts::OS, rc::Rc, time::Duration};
 
 use anyhow::bail;
 use indexmap::IndexMap;
@@ -46,11 +46,7 @@ impl<'a> Val<'a> {
 
   fn create(value: &'a Value, trace: Trace) -> anyhow::Result<Self> {
     if let Value::Mapping(map) = value {
-      if map
-        .into_iter()
-        .next()
-        .map_or(false, |(k, _)| k.eq("$select"))
-      {
+      if map.into_iter().next().is_some_and(|(k, _)| k.eq("$select")) {
         let (v, t) = Self::select(map, trace)?;
         return Self::create(v, t);
       }
@@ -62,6 +58,11 @@ impl<'a> Val<'a> {
     self.0
   }
 
+  /// Wraps a value derived from this one, keeping the trace for errors.
+  pub fn derive<'b>(&self, value: &'b Value) -> anyhow::Result<Val<'b>> {
+    Val::create(value, self.1.clone())
+  }
+
   fn select(
     map: &'a serde_yaml::Mapping,
     trace: Trace,
@@ -85,7 +86,12 @@ impl<'a> Val<'a> {
   }
 
   pub fn error_at<T: AsRef<str>>(&self, msg: T) -> anyhow::Error {
-    anyhow::format_err!("{} at {}", msg.as_ref(), self.1.to_string())
+    anyhow::Error::msg(self.message_at(msg))
+  }
+
+  /// Formats a message with the location of the value, e.g. for warnings.
+  pub fn message_at<T: AsRef<str>>(&self, msg: T) -> String {
+    format!("{} at {}", msg.as_ref(), self.1)
   }
 
   pub fn as_bool(&self) -> anyhow::Result<bool> {
@@ -104,13 +110,38 @@ impl<'a> Val<'a> {
       .map(|x| x as usize)
   }
 
+  /// Accepts a number of seconds (`1.5`) or a string with a unit suffix
+  /// (`300ms`, `5s`, `2m`, `1h`).
+  pub fn as_duration(&self) -> anyhow::Result<Duration> {
+    let duration = match self.0 {
+      Value::Number(n) => n
+        .as_f64()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+      Value::String(s) => parse_duration(s),
+      _ => None,
+    };
+    duration
+      .ok_or_else(|| anyhow::format_err!("Expected duration at {}", self.1))
+  }
+
+  /// Accepts a number of bytes or a string with a unit suffix (`512KB`,
+  /// `10MB`, `1GB`).
+  pub fn as_size(&self) -> anyhow::Result<u64> {
+    let size = match self.0 {
+      Value::Number(n) => n.as_u64(),
+      Value::String(s) => parse_size(s),
+      _ => None,
+    };
+    size.ok_or_else(|| anyhow::format_err!("Expected size at {}", self.1))
+  }
+
   pub fn as_str(&self) -> anyhow::Result<&str> {
     self.0.as_str().ok_or_else(|| {
       anyhow::format_err!("Expected string at {}", self.1.to_string())
     })
   }
 
-  pub fn as_array(&self) -> anyhow::Result<Vec<Val>> {
+  pub fn as_array(&self) -> anyhow::Result<Vec<Val<'_>>> {
     self
       .0
       .as_sequence()
@@ -123,7 +154,7 @@ impl<'a> Val<'a> {
       .collect::<anyhow::Result<Vec<_>>>()
   }
 
-  pub fn as_object(&self) -> anyhow::Result<IndexMap<Value, Val>> {
+  pub fn as_object(&self) -> anyhow::Result<IndexMap<Value, Val<'_>>> {
     self
       .0
       .as_mapping()
@@ -146,6 +177,38 @@ impl<'a> Val<'a> {
   }
 }
 
+pub fn parse_duration(s: &str) -> Option<Duration> {
+  let s = s.trim();
+  let split = s
+    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+    .unwrap_or(s.len());
+  let (num, unit) = s.split_at(split);
+  let num: f64 = num.parse().ok()?;
+  let secs = match unit.trim() {
+    "ms" => num / 1000.0,
+    "" | "s" => num,
+    "m" => num * 60.0,
+    "h" => num * 60.0 * 60.0,
+    _ => return None,
+  };
+  Duration::try_from_secs_f64(secs).ok()
+}
+
+pub fn parse_size(s: &str) -> Option<u64> {
+  let s = s.trim();
+  let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  let (num, unit) = s.split_at(split);
+  let num: u64 = num.parse().ok()?;
+  let factor: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+    "" | "B" => 1,
+    "K" | "KB" => 1 << 10,
+    "M" | "MB" => 1 << 20,
+    "G" | "GB" => 1 << 30,
+    _ => return None,
+  };
+  num.checked_mul(factor)
+}
+
 pub fn value_to_string(value: &Value) -> anyhow::Result<String> {
   match value {
     Value::Null => Ok("null".to_string()),
@@ -160,3 +223,37 @@ pub fn value_to_string(value: &Value) -> anyhow::Result<String> {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn durations() {
+    assert_eq!(parse_duration("300ms"), Some(Duration::from_millis(300)));
+    assert_eq!(parse_duration("5s"), Some(Duration::from_secs(5)));
+    assert_eq!(parse_duration("1.5"), Some(Duration::from_millis(1500)));
+    assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+    assert_eq!(parse_duration("5 s"), Some(Duration::from_secs(5)));
+    assert_eq!(parse_duration("5d"), None);
+    assert_eq!(parse_duration("s"), None);
+    assert_eq!(parse_duration(""), None);
+    assert_eq!(parse_duration("99999999999999999999h"), None);
+
+    let value = serde_yaml::from_str("1e30").unwrap();
+    assert!(Val::new(&value).unwrap().as_duration().is_err());
+    let value = serde_yaml::from_str("-1").unwrap();
+    assert!(Val::new(&value).unwrap().as_duration().is_err());
+  }
+
+  #[test]
+  fn sizes() {
+    assert_eq!(parse_size("100"), Some(100));
+    assert_eq!(parse_size("512KB"), Some(512 * 1024));
+    assert_eq!(parse_size("10MB"), Some(10 * 1024 * 1024));
+    assert_eq!(parse_size("1 gb"), Some(1024 * 1024 * 1024));
+    assert_eq!(parse_size("1.5MB"), None);
+    assert_eq!(parse_size("MB"), None);
+  }
+}